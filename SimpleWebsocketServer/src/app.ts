@@ -1,19 +1,147 @@
+import * as crypto from 'crypto';
+import * as dns from 'dns';
+import * as http from 'http';
+import * as https from 'https';
+import * as net from 'net';
 import WebSocket, { WebSocketServer } from 'ws';
 
 const PORT = process.env.PORT ? parseInt(process.env.PORT) : 8080;
+const DEFAULT_ROOM = 'general';
+const ROOM_PATTERN = /^[a-z0-9][a-z0-9-]{0,31}$/;
+// Keep in sync with `validate_username` in YewChat's `protocol.rs`.
+const NICK_PATTERN = /^[A-Za-z0-9_.-]{2,20}$/;
+const RESERVED_NICKS = ['admin', 'server', 'system', 'you', 'initial'];
+// Messages kept per room, and how many are sent per `history` frame.
+const HISTORY_LIMIT = 500;
+const HISTORY_PAGE = 50;
+// Longest message text, in UTF-16 code units. Keep in sync with `MAX_MESSAGE_LEN` in YewChat's `protocol.rs`.
+const MAX_MESSAGE_LEN = 4000;
+// A typing start is dropped after this long unless the client repeats it.
+const TYPING_EXPIRY_MS = 6 * 1000;
+// How many delivered frames are remembered to recognise messages sent again after a reconnect.
+const DELIVERED_LIMIT = 1000;
+// Different emojis one message can be reacted to with.
+const MAX_REACTIONS = 20;
+// A single emoji: a flag, a keycap like 1️⃣, or a pictograph shown as an emoji, possibly with a
+// skin tone and joined to others with ZWJs (as in 👩‍💻). Symbols shown as text by default, like
+// ©, only count when followed by U+FE0F asking for the emoji.
+const EMOJI_PATTERN = /^(?:\p{Regional_Indicator}{2}|\u{1F3F4}[\u{E0020}-\u{E007E}]+\u{E007F}|[0-9#*]\uFE0F\u20E3|(?:(?!\p{Regional_Indicator})\p{Emoji_Presentation}\uFE0F?|\p{Extended_Pictographic}\uFE0F|\p{Emoji_Modifier_Base}(?=\p{Emoji_Modifier}))\p{Emoji_Modifier}?(?:\u200D\p{Extended_Pictographic}\uFE0F?\p{Emoji_Modifier}?)*)$/u;
+// Sockets are pinged this often and dropped when they didn't answer the previous ping.
+const HEARTBEAT_MS = 5 * 1000;
+// What clients may choose; users without a socket are 'offline'.
+const PRESENCE_STATES = ['online', 'away', 'doNotDisturb'];
+const AVATAR_STYLES = ['adventurer-neutral', 'bottts-neutral', 'fun-emoji', 'identicon', 'initials', 'pixel-art', 'thumbs'];
+// Signs session tokens. Set it to keep tokens valid across restarts.
+const AUTH_SECRET = process.env.AUTH_SECRET || crypto.randomBytes(32).toString('hex');
+const TOKEN_TTL_MS = 7 * 24 * 60 * 60 * 1000;
+// Link previews: how long a page may take to answer, how much of it is read, how many redirects
+// are followed, how many previews are kept and how long a failed fetch is remembered.
+const PREVIEW_TIMEOUT_MS = 5 * 1000;
+const PREVIEW_MAX_BYTES = 256 * 1024;
+const PREVIEW_REDIRECTS = 3;
+const PREVIEW_CACHE_LIMIT = 500;
+const PREVIEW_RETRY_MS = 60 * 1000;
+const ENTITIES = new Map([['amp', '&'], ['lt', '<'], ['gt', '>'], ['quot', '"'], ['apos', "'"], ['nbsp', ' ']]);
+
 interface User {
     ws: WebSocket;
     nick: String;
     isAlive: boolean;
+    rooms: Set<string>;
+    status: string;
+}
+
+interface Account {
+    nick: string;
+    salt: string;
+    hash: string;
 }
 
 interface Message {
     messageType: String;
     data: String;
     dataArray: String[];
+    password?: string;
+    token?: string;
+    nonce?: string;
+    room?: string;
+    to?: string;
+    before?: number;
+    id?: number;
+    emoji?: string;
+    parent?: number;
+    status?: string;
+    profile?: Profile;
+    url?: string;
+}
+
+interface Profile {
+    displayName: string;
+    statusText: string;
+    pronouns: string;
+    avatarSeed: string;
+    avatarStyle: string;
+}
+
+interface LinkPreview {
+    title: string;
+    description: string;
+    image?: string;
+    siteName: string;
+}
+
+// A fetched page, with the address it was found at after redirects.
+interface Page {
+    url: string;
+    html: string;
+}
+
+interface ChatMessage {
+    id: number;
+    from: String;
+    message: String;
+    time: number;
+    room?: string;
+    to?: String;
+    nonce?: string;
+    edited?: number;
+    deleted?: boolean;
+    reactions?: Reaction[];
+    parent?: number;
+}
+
+// A room, or the other user of a direct conversation.
+interface Conversation {
+    room?: string;
+    to?: string;
+}
+
+interface Reaction {
+    emoji: string;
+    users: String[];
 }
 
 let users: User[] = [];
+let nextMessageId = 1;
+const history = new Map<string, ChatMessage[]>();
+// Recent direct messages, kept so their senders can still edit or delete them.
+let directs: ChatMessage[] = [];
+// Stand-in user table, keyed by lowercase nick: the first login under a nick claims it.
+const accounts = new Map<string, Account>();
+// The nick each socket has authenticated as.
+const sessions = new Map<WebSocket, string>();
+// Expiry timers of everyone typing, keyed by nick and room or `@recipient`.
+const typing = new Map<string, ReturnType<typeof setTimeout>>();
+// Frames delivered for recent messages, keyed by sender and nonce.
+const delivered = new Map<string, string>();
+// Id of the last message each user has read, keyed by `#room` or `@` and both nicks.
+const reads = new Map<string, Map<String, number>>();
+// Presence last announced for every nick seen since startup, and when the offline ones left.
+const presence = new Map<String, string>();
+const lastSeen = new Map<String, number>();
+const profiles = new Map<String, Profile>();
+// Link previews by URL, fetched once for everyone asking; undefined for pages without one.
+const previews = new Map<string, Promise<LinkPreview | undefined>>();
 
 console.log(`Listening on port ${PORT}`);
 const wss = new WebSocketServer({ port: PORT });
@@ -25,40 +153,686 @@ wss.on('connection', (ws: WebSocket) => {
         const raw_data = data.toString();
         try {
             const parsed_data: Message = JSON.parse(raw_data);
+            const sender = users.find((u) => u.ws === ws);
             switch (parsed_data.messageType) {
+                case 'auth':
+                    const result = authenticate(parsed_data);
+                    if (result.nick) {
+                        sessions.set(ws, result.nick);
+                        send(ws, JSON.stringify({ messageType: 'authOk', data: result.nick, token: issueToken(result.nick) }));
+                    } else {
+                        sessions.delete(ws);
+                        send(ws, JSON.stringify({ messageType: 'authRejected', reason: result.reason }));
+                    }
+                    break;
                 case 'register':
-                    users.push({ ws, nick: parsed_data.data, isAlive: true });
-                    broadcast(JSON.stringify({ messageType: 'users', dataArray: users.map((u) => u.nick) }));
+                    const rejection = registrationError(parsed_data.data, ws);
+                    if (rejection) {
+                        send(ws, JSON.stringify({ messageType: 'registerRejected', reason: rejection }));
+                        break;
+                    }
+                    // Registering again under the same nick (e.g. after a reload of the chat view) keeps
+                    // the room memberships; a new nick starts over.
+                    const again = sender && sender.nick === parsed_data.data;
+                    const rooms = sender && again ? sender.rooms : new Set([DEFAULT_ROOM]);
+                    const status = sender && again ? sender.status : 'online';
+                    users = users.filter((u) => u.ws !== ws);
+                    users.push({ ws, nick: parsed_data.data, isAlive: true, rooms, status });
+                    send(ws, JSON.stringify({ messageType: 'registerAck', data: parsed_data.data }));
+                    broadcast(usersMessage());
+                    broadcast(roomsMessage());
+                    presence.forEach((_, nick) => send(ws, presenceMessage(nick)));
+                    profiles.forEach((profile, nick) => send(ws, profileMessage(nick, profile)));
+                    updatePresence(parsed_data.data);
+                    if (sender && !again) {
+                        updatePresence(sender.nick);
+                    }
+                    rooms.forEach((room) => {
+                        send(ws, historyMessage(room));
+                        send(ws, readsMessage(parsed_data.data, { room }));
+                    });
+                    directPeers(parsed_data.data).forEach((to) => send(ws, readsMessage(parsed_data.data, { to })));
                     break;
-                case 'message':
-                    const sender = users.find((u) => u.ws === ws);
+                case 'unregister':
+                    sessions.delete(ws);
                     if (sender) {
-                        broadcast(
-                            JSON.stringify({
-                                messageType: 'message',
-                                data: JSON.stringify({
-                                    from: sender.nick,
-                                    message: parsed_data.data,
-                                    time: Date.now(),
-                                }),
-                            })
-                        );
+                        users = users.filter((u) => u !== sender);
+                        broadcast(usersMessage());
+                        broadcast(roomsMessage());
+                        updatePresence(sender.nick);
+                    }
+                    break;
+                case 'join':
+                    if (sender && isValidRoom(parsed_data.room)) {
+                        sender.rooms.add(parsed_data.room);
+                        broadcast(roomsMessage());
+                        send(ws, historyMessage(parsed_data.room));
+                        send(ws, readsMessage(sender.nick, { room: parsed_data.room }));
+                    }
+                    break;
+                case 'historyRequest':
+                    if (sender && sender.rooms.has(parsed_data.room as string)) {
+                        send(ws, historyMessage(parsed_data.room as string, parsed_data.before));
+                    }
+                    break;
+                case 'leave':
+                    if (sender && parsed_data.room !== DEFAULT_ROOM && sender.rooms.delete(parsed_data.room as string)) {
+                        broadcast(roomsMessage());
+                    }
+                    break;
+                case 'message':
+                    // Clients from before rooms existed send no room at all.
+                    const room = parsed_data.room === undefined ? DEFAULT_ROOM : parsed_data.room;
+                    if (!sender) {
+                        break;
+                    }
+                    const refusal = !isValidText(parsed_data.data)
+                        ? `Messages need some text, up to ${MAX_MESSAGE_LEN} characters.`
+                        : !isValidRoom(room)
+                        ? 'That is not a valid room name.'
+                        : !sender.rooms.has(room)
+                        ? `You are not in #${room}.`
+                        : undefined;
+                    if (refusal) {
+                        send(ws, JSON.stringify({ messageType: 'messageRejected', nonce: nonceOf(parsed_data), reason: refusal }));
+                        break;
+                    }
+                    if (!redeliver(ws, sender, parsed_data)) {
+                        const message = {
+                            id: nextMessageId++,
+                            from: sender.nick,
+                            message: parsed_data.data,
+                            time: Date.now(),
+                            room,
+                            nonce: nonceOf(parsed_data),
+                            parent: parentOf(parsed_data, (m) => !m.to && m.room === room),
+                        };
+                        const frame = JSON.stringify({ messageType: 'message', data: message });
+                        remember(room, message);
+                        markDelivered(sender, parsed_data, frame);
+                        setTyping(sender.nick, { room }, false);
+                        sendToRoom(room, frame);
+                    }
+                    break;
+                case 'direct':
+                    const recipients = users.filter((u) => u.nick === parsed_data.to);
+                    if (!sender || !isValidText(parsed_data.data)) {
+                        break;
+                    }
+                    if (recipients.length === 0) {
+                        const known = typeof parsed_data.to === 'string' && accounts.has(parsed_data.to.toLowerCase());
+                        send(ws, JSON.stringify({
+                            messageType: 'directRejected',
+                            to: parsed_data.to,
+                            nonce: nonceOf(parsed_data),
+                            reason: known ? `${parsed_data.to} is offline.` : `There is no user called ${parsed_data.to}.`,
+                        }));
+                        break;
+                    }
+                    if (!redeliver(ws, sender, parsed_data)) {
+                        // Every window of both users gets a copy.
+                        const copies = users.filter((u) => u.nick === parsed_data.to || u.nick === sender.nick);
+                        const message = {
+                            id: nextMessageId++,
+                            from: sender.nick,
+                            to: parsed_data.to,
+                            message: parsed_data.data,
+                            time: Date.now(),
+                            nonce: nonceOf(parsed_data),
+                            parent: parentOf(
+                                parsed_data,
+                                (m) => !!m.to && [m.from, m.to].includes(sender.nick) && [m.from, m.to].includes(parsed_data.to as string)
+                            ),
+                        };
+                        const direct = JSON.stringify({ messageType: 'direct', data: message });
+                        directs = [...directs, message].slice(-HISTORY_LIMIT);
+                        markDelivered(sender, parsed_data, direct);
+                        setTyping(sender.nick, { to: parsed_data.to }, false);
+                        copies.forEach((u) => send(u.ws, direct));
+                    }
+                    break;
+                case 'edit':
+                case 'delete':
+                    // Only the sender may change a message, from any socket registered under their nick.
+                    const target = findMessage(parsed_data.id);
+                    if (!sender || !target || target.from !== sender.nick || target.deleted) {
+                        break;
+                    }
+                    if (parsed_data.messageType === 'delete') {
+                        target.message = '';
+                        target.deleted = true;
+                        target.reactions = undefined;
+                    } else if (isValidText(parsed_data.data)) {
+                        target.message = parsed_data.data;
+                        target.edited = Date.now();
+                    } else {
+                        break;
+                    }
+                    sendUpdate(target);
+                    break;
+                case 'react':
+                    const reacted = findMessage(parsed_data.id);
+                    if (sender && reacted && !reacted.deleted && canSee(sender, reacted) && isValidEmoji(parsed_data.emoji)) {
+                        if (toggleReaction(reacted, parsed_data.emoji, sender.nick)) {
+                            sendUpdate(reacted);
+                        }
+                    }
+                    break;
+                case 'typingStart':
+                case 'typingStop':
+                    if (sender && (parsed_data.room === undefined || sender.rooms.has(parsed_data.room))) {
+                        setTyping(sender.nick, parsed_data, parsed_data.messageType === 'typingStart');
+                    }
+                    break;
+                case 'read':
+                    if (sender && typeof parsed_data.id === 'number' && (parsed_data.room === undefined || sender.rooms.has(parsed_data.room))) {
+                        markRead(sender.nick, parsed_data, parsed_data.id);
+                    }
+                    break;
+                case 'presence':
+                    if (sender && PRESENCE_STATES.includes(parsed_data.status as string)) {
+                        sender.status = parsed_data.status as string;
+                        updatePresence(sender.nick);
+                    }
+                    break;
+                case 'updateProfile':
+                    if (sender && typeof parsed_data.profile === 'object' && parsed_data.profile !== null) {
+                        const profile = cleanProfile(parsed_data.profile);
+                        profiles.set(sender.nick, profile);
+                        broadcast(profileMessage(sender.nick, profile));
+                    }
+                    break;
+                case 'previewRequest':
+                    if (sender && typeof parsed_data.url === 'string') {
+                        const url = parsed_data.url;
+                        linkPreview(url).then((preview) => send(ws, JSON.stringify({ messageType: 'preview', url, preview })));
                     }
             }
         } catch (e) {
             console.log('Error in message', e);
         }
     });
+
+    ws.on('pong', () => {
+        users.filter((u) => u.ws === ws).forEach((u) => (u.isAlive = true));
+    });
+
+    ws.on('close', () => {
+        sessions.delete(ws);
+        const remaining = users.filter((u) => u.ws !== ws);
+        if (remaining.length !== users.length) {
+            const gone = users.filter((u) => u.ws === ws);
+            users = remaining;
+            broadcast(usersMessage());
+            broadcast(roomsMessage());
+            gone.forEach((u) => updatePresence(u.nick));
+        }
+    });
 });
 
 const interval = setInterval(function ping() {
+    // A socket that went away without closing doesn't answer; terminating it emits 'close'.
+    users.filter((u) => !u.isAlive).forEach((u) => u.ws.terminate());
     const current_clients = Array.from(wss.clients);
     const updated_users = users.filter((u) => current_clients.includes(u.ws));
     if (updated_users.length !== users.length) {
+        const gone = users.filter((u) => !updated_users.includes(u));
         users = updated_users;
-        broadcast(JSON.stringify({ messageType: 'users', dataArray: users.map((u) => u.nick) }));
+        broadcast(usersMessage());
+        broadcast(roomsMessage());
+        gone.forEach((u) => updatePresence(u.nick));
+    }
+    users.forEach((u) => {
+        u.isAlive = false;
+        u.ws.ping();
+    });
+}, HEARTBEAT_MS);
+
+const nickError = (nick: any): string | undefined => {
+    if (typeof nick !== 'string' || !NICK_PATTERN.test(nick)) {
+        return 'Usernames are 2 to 20 letters, digits, ".", "_" or "-".';
+    }
+    if (RESERVED_NICKS.includes(nick.toLowerCase())) {
+        return `"${nick}" is reserved.`;
+    }
+    return undefined;
+};
+
+// Only the nick a socket authenticated as can be registered on it. The same account may be
+// registered from several windows at once.
+const registrationError = (nick: any, ws: WebSocket): string | undefined => {
+    const invalid = nickError(nick);
+    if (invalid) {
+        return invalid;
+    }
+    if (sessions.get(ws) !== nick) {
+        return 'Log in before registering.';
+    }
+    return undefined;
+};
+
+// Checks a password or session token, returning the account's nick as it was first claimed.
+const authenticate = (auth: Message) => {
+    const invalid = nickError(auth.data);
+    if (invalid) {
+        return { reason: invalid };
+    }
+    if (typeof auth.token === 'string') {
+        const nick = verifyToken(auth.token);
+        if (nick && nick.toLowerCase() === auth.data.toLowerCase()) {
+            return { nick };
+        }
+        return { reason: 'Your session has expired, please log in again.' };
+    }
+    if (typeof auth.password !== 'string' || auth.password.length === 0) {
+        return { reason: 'A password is required.' };
+    }
+    const account = accounts.get(auth.data.toLowerCase());
+    if (!account) {
+        const salt = crypto.randomBytes(16).toString('hex');
+        accounts.set(auth.data.toLowerCase(), { nick: auth.data as string, salt, hash: hashPassword(auth.password, salt) });
+        return { nick: auth.data as string };
+    }
+    if (!sameSecret(hashPassword(auth.password, account.salt), account.hash)) {
+        return { reason: 'Wrong username or password.' };
+    }
+    return { nick: account.nick };
+};
+
+const hashPassword = (password: string, salt: string) => crypto.scryptSync(password, salt, 32).toString('hex');
+
+const sign = (payload: string) => crypto.createHmac('sha256', AUTH_SECRET).update(payload).digest('hex');
+
+const sameSecret = (a: string, b: string) => a.length === b.length && crypto.timingSafeEqual(Buffer.from(a), Buffer.from(b));
+
+// Tokens are `<nick>.<expiry in ms>.<signature>`; nicks may contain dots, so split from the right.
+const issueToken = (nick: string) => {
+    const payload = `${nick}.${Date.now() + TOKEN_TTL_MS}`;
+    return `${payload}.${sign(payload)}`;
+};
+
+const verifyToken = (token: string): string | undefined => {
+    const payload = token.slice(0, token.lastIndexOf('.'));
+    const signature = token.slice(token.lastIndexOf('.') + 1);
+    if (!sameSecret(signature, sign(payload)) || Number(payload.slice(payload.lastIndexOf('.') + 1)) < Date.now()) {
+        return undefined;
+    }
+    return payload.slice(0, payload.lastIndexOf('.'));
+};
+
+const usersMessage = () =>
+    JSON.stringify({ messageType: 'users', dataArray: Array.from(new Set(users.map((u) => u.nick))) });
+
+// Combined over all windows of `nick`: do-not-disturb in any wins, then being online in any.
+const presenceOf = (nick: String) => {
+    const statuses = users.filter((u) => u.nick === nick).map((u) => u.status);
+    if (statuses.length === 0) {
+        return 'offline';
+    }
+    if (statuses.includes('doNotDisturb')) {
+        return 'doNotDisturb';
+    }
+    return statuses.includes('online') ? 'online' : 'away';
+};
+
+const presenceMessage = (nick: String) =>
+    JSON.stringify({ messageType: 'presence', data: nick, status: presence.get(nick), lastSeen: lastSeen.get(nick) });
+
+// Tells everyone when the presence of `nick` has changed.
+const updatePresence = (nick: String) => {
+    const status = presenceOf(nick);
+    if (presence.get(nick) === status) {
+        return;
+    }
+    presence.set(nick, status);
+    if (status === 'offline') {
+        lastSeen.set(nick, Date.now());
+    } else {
+        lastSeen.delete(nick);
+    }
+    broadcast(presenceMessage(nick));
+};
+
+const profileMessage = (nick: String, profile: Profile) => JSON.stringify({ messageType: 'profile', data: nick, profile });
+
+// Only known fields, trimmed to the lengths clients allow.
+const cleanProfile = (profile: Profile): Profile => {
+    const text = (value: any, max: number) => (typeof value === 'string' ? value.trim().slice(0, max) : '');
+    return {
+        displayName: text(profile.displayName, 32),
+        statusText: text(profile.statusText, 80),
+        pronouns: text(profile.pronouns, 20),
+        avatarSeed: text(profile.avatarSeed, 32),
+        avatarStyle: AVATAR_STYLES.includes(profile.avatarStyle) ? profile.avatarStyle : '',
+    };
+};
+
+const linkPreview = (url: string): Promise<LinkPreview | undefined> => {
+    const cached = previews.get(url);
+    if (cached) {
+        return cached;
+    }
+    const preview: Promise<LinkPreview | undefined> = fetchPage(url, PREVIEW_REDIRECTS).then(parsePreview, () => {
+        // The page may just have been down or slow, so it's fetched again after a while.
+        setTimeout(() => {
+            if (previews.get(url) === preview) {
+                previews.delete(url);
+            }
+        }, PREVIEW_RETRY_MS);
+        return undefined;
+    });
+    if (previews.size >= PREVIEW_CACHE_LIMIT) {
+        previews.delete(previews.keys().next().value);
+    }
+    previews.set(url, preview);
+    return preview;
+};
+
+// Fetches the HTML page at `address`, relative to `base`. Only public http(s) addresses are
+// fetched, so clients can't use the server to reach the network it runs in.
+const fetchPage = (address: string, redirects: number, base?: string): Promise<Page> =>
+    new Promise((resolve, reject) => {
+        const url = new URL(address, base);
+        const host = url.hostname.replace(/^\[|\]$/g, '');
+        if (!['http:', 'https:'].includes(url.protocol) || url.username || url.password || (net.isIP(host) && !isPublicAddress(host))) {
+            throw new Error(`Not fetching ${address}`);
+        }
+        const options = {
+            lookup: publicLookup,
+            timeout: PREVIEW_TIMEOUT_MS,
+            headers: { accept: 'text/html', 'user-agent': 'YewChat link preview' },
+        };
+        const received = (response: any) => {
+            const status = response.statusCode;
+            const location = response.headers.location;
+            if (status >= 300 && status < 400 && location && redirects > 0) {
+                response.resume();
+                fetchPage(location, redirects - 1, url.toString()).then(resolve, reject);
+            } else if (status !== 200 || !/^text\/html/i.test(response.headers['content-type'] || '')) {
+                response.resume();
+                reject(new Error(`${address} answered ${status}`));
+            } else {
+                // Only the head matters, so long pages are cut short.
+                let html = '';
+                response.setEncoding('utf8');
+                response.on('data', (chunk: string) => {
+                    html += chunk;
+                    if (html.length >= PREVIEW_MAX_BYTES) {
+                        response.destroy();
+                        resolve({ url: url.toString(), html });
+                    }
+                });
+                response.on('end', () => resolve({ url: url.toString(), html }));
+                response.on('error', reject);
+            }
+        };
+        const request = url.protocol === 'https:' ? https.get(url, options, received) : http.get(url, options, received);
+        request.on('timeout', () => request.destroy(new Error(`${address} timed out`)));
+        request.on('error', reject);
+    });
+
+// `dns.lookup`, failing for host names with any address that isn't public.
+const publicLookup = (hostname: string, options: any, callback: any) => {
+    dns.lookup(hostname, { ...options, all: true }, (error, addresses) => {
+        if (error || addresses.length === 0 || !addresses.every((a) => isPublicAddress(a.address))) {
+            callback(error || new Error(`${hostname} is not a public address`));
+        } else if (options.all) {
+            callback(null, addresses);
+        } else {
+            callback(null, addresses[0].address, addresses[0].family);
+        }
+    });
+};
+
+// Whether `address` is outside any local network: not loopback, private, link-local or multicast.
+const isPublicAddress = (address: string) => {
+    const v4 = address.replace(/^::ffff:/i, '');
+    if (net.isIPv4(v4)) {
+        const [a, b] = v4.split('.').map(Number);
+        return !(
+            a === 0 ||
+            a === 10 ||
+            a === 127 ||
+            a >= 224 ||
+            (a === 100 && b >= 64 && b < 128) ||
+            (a === 169 && b === 254) ||
+            (a === 172 && b >= 16 && b < 32) ||
+            (a === 192 && b === 168)
+        );
+    }
+    return net.isIPv6(address) && !/^(::1?$|::ffff:|f[cd]|fe[89ab]|ff)/i.test(address);
+};
+
+// The title, description and picture a page declares for sharing, or its <title>. Pages with
+// neither a title nor a description have no preview.
+const parsePreview = (page: Page): LinkPreview | undefined => {
+    const meta = (name: string) => {
+        const tag = page.html.match(new RegExp(`<meta[^>]+(?:property|name)=["']${name}["'][^>]*>`, 'i'));
+        const content = tag && tag[0].match(/content=(?:"([^"]*)"|'([^']*)')/i);
+        return content ? decodeEntities(content[1] || content[2] || '') : '';
+    };
+    const text = (value: string, max: number) => value.replace(/\s+/g, ' ').trim().slice(0, max);
+    const title = page.html.match(/<title[^>]*>([^<]*)</i);
+    const preview = {
+        title: text(meta('og:title') || (title ? decodeEntities(title[1]) : ''), 120),
+        description: text(meta('og:description') || meta('description'), 300),
+        image: absoluteUrl(meta('og:image'), page.url),
+        siteName: text(meta('og:site_name'), 60) || new URL(page.url).hostname,
+    };
+    return preview.title || preview.description ? preview : undefined;
+};
+
+// `value` resolved against `base`, if it is an http(s) address.
+const absoluteUrl = (value: string, base: string): string | undefined => {
+    try {
+        const url = new URL(value, base);
+        return value && ['http:', 'https:'].includes(url.protocol) ? url.toString() : undefined;
+    } catch (e) {
+        return undefined;
+    }
+};
+
+const decodeEntities = (text: string) =>
+    text.replace(/&(#x[0-9a-f]+|#[0-9]+|[a-z]+);/gi, (entity: string, name: string) => {
+        if (name[0] === '#') {
+            const hex = name[1] === 'x' || name[1] === 'X';
+            const code = hex ? parseInt(name.slice(2), 16) : parseInt(name.slice(1), 10);
+            return code > 0 && code <= 0x10ffff ? String.fromCodePoint(code) : entity;
+        }
+        return ENTITIES.get(name.toLowerCase()) || entity;
+    });
+
+// Tells the others in a room, or the recipient of a direct message, whether `nick` is typing there.
+const setTyping = (nick: String, target: Conversation, active: boolean) => {
+    const room = isValidRoom(target.room) ? target.room : undefined;
+    const to = room ? undefined : target.to;
+    if (!room && typeof to !== 'string') {
+        return;
+    }
+    const key = `${nick}\n${room || '@' + to}`;
+    const wasActive = typing.has(key);
+    clearTimeout(typing.get(key));
+    typing.delete(key);
+    if (active) {
+        typing.set(key, setTimeout(() => setTyping(nick, target, false), TYPING_EXPIRY_MS));
+    }
+    if (active !== wasActive) {
+        const frame = JSON.stringify({ messageType: 'typing', data: nick, room, active });
+        users.filter((u) => u.nick !== nick && (room ? u.rooms.has(room) : u.nick === to)).forEach((u) => send(u.ws, frame));
+    }
+};
+
+const readKey = (nick: String, target: Conversation): string | undefined => {
+    if (isValidRoom(target.room)) {
+        return `#${target.room}`;
+    }
+    if (typeof target.to === 'string' && target.to !== nick && !nickError(target.to)) {
+        return '@' + [nick, target.to].sort().join('\n');
+    }
+    return undefined;
+};
+
+// Where everyone in the conversation has read up to, as seen by `nick`.
+const readsMessage = (nick: String, target: Conversation) => {
+    const positions: Record<string, number> = {};
+    const key = readKey(nick, target);
+    (key && reads.get(key) || new Map<String, number>()).forEach((id, reader) => (positions[reader as string] = id));
+    return JSON.stringify({ messageType: 'reads', room: target.room, peer: target.room ? undefined : target.to, positions });
+};
+
+// Moves `nick`'s read position forward and tells everyone in the conversation.
+const markRead = (nick: String, target: Conversation, id: number) => {
+    const key = readKey(nick, target);
+    const positions = key && reads.get(key) || new Map<String, number>();
+    if (!key || id >= nextMessageId || (positions.get(nick) || 0) >= id) {
+        return;
+    }
+    positions.set(nick, id);
+    reads.set(key, positions);
+    if (isValidRoom(target.room)) {
+        sendToRoom(target.room, readsMessage(nick, target));
+    } else {
+        const to = target.to as string;
+        users.filter((u) => u.nick === nick).forEach((u) => send(u.ws, readsMessage(nick, { to })));
+        users.filter((u) => u.nick === to).forEach((u) => send(u.ws, readsMessage(to, { to: nick as string })));
+    }
+};
+
+// Everyone `nick` has exchanged direct messages with.
+const directPeers = (nick: String) => {
+    const peers = new Set<string>();
+    directs.forEach((m) => {
+        if (m.from === nick || m.to === nick) {
+            peers.add((m.from === nick ? m.to : m.from) as string);
+        }
+    });
+    return Array.from(peers);
+};
+
+// Echoed back so the sender can match the message to the copy it is already showing.
+const nonceOf = (message: Message): string | undefined =>
+    typeof message.nonce === 'string' && message.nonce.length <= 64 ? message.nonce : undefined;
+
+// A message the sender didn't see the echo of may come again; only the sender gets it a second time.
+const redeliver = (ws: WebSocket, sender: User, message: Message): boolean => {
+    const nonce = nonceOf(message);
+    const frame = nonce && delivered.get(`${sender.nick}\n${nonce}`);
+    if (frame) {
+        send(ws, frame);
+        return true;
+    }
+    return false;
+};
+
+const markDelivered = (sender: User, message: Message, frame: string) => {
+    const nonce = nonceOf(message);
+    if (nonce) {
+        delivered.set(`${sender.nick}\n${nonce}`, frame);
+        if (delivered.size > DELIVERED_LIMIT) {
+            delivered.delete(delivered.keys().next().value as string);
+        }
+    }
+};
+
+// The message replied to, as long as it is part of the same conversation.
+const parentOf = (message: Message, sameConversation: (m: ChatMessage) => boolean): number | undefined => {
+    const parent = findMessage(message.parent);
+    return parent && sameConversation(parent) ? parent.id : undefined;
+};
+
+// Message text: a string with something besides whitespace in it, and not too long.
+const isValidText = (text: any): text is string => typeof text === 'string' && text.trim().length > 0 && text.length <= MAX_MESSAGE_LEN;
+
+const isValidRoom = (room: any): room is string => typeof room === 'string' && ROOM_PATTERN.test(room);
+
+const roomsMessage = () => {
+    const rooms = new Map<string, String[]>([[DEFAULT_ROOM, []]]);
+    users.forEach((u) =>
+        u.rooms.forEach((room) => {
+            const members = rooms.get(room) || [];
+            rooms.set(room, members.includes(u.nick) ? members : [...members, u.nick]);
+        })
+    );
+    return JSON.stringify({
+        messageType: 'rooms',
+        rooms: Array.from(rooms, ([id, members]) => ({ id, members })),
+    });
+};
+
+const findMessage = (id: any): ChatMessage | undefined => {
+    for (const messages of [directs, ...Array.from(history.values())]) {
+        const found = messages.find((m) => m.id === id);
+        if (found) {
+            return found;
+        }
+    }
+    return undefined;
+};
+
+// Whether `user` got `message` in the first place.
+const canSee = (user: User, message: ChatMessage) =>
+    message.to ? user.nick === message.to || user.nick === message.from : user.rooms.has(message.room as string);
+
+const isValidEmoji = (emoji: any): emoji is string =>
+    typeof emoji === 'string' && emoji.length <= 32 && EMOJI_PATTERN.test(emoji);
+
+// Adds `nick`'s reaction, or removes it if they already reacted with `emoji`. Returns false when
+// nothing changed because the message has as many different reactions as it may have.
+const toggleReaction = (message: ChatMessage, emoji: string, nick: String): boolean => {
+    const reactions = message.reactions || [];
+    const reaction = reactions.find((r) => r.emoji === emoji);
+    if (!reaction && reactions.length >= MAX_REACTIONS) {
+        return false;
+    }
+    if (!reaction) {
+        reactions.push({ emoji, users: [nick] });
+    } else if (reaction.users.includes(nick)) {
+        reaction.users = reaction.users.filter((u) => u !== nick);
+    } else {
+        reaction.users.push(nick);
+    }
+    const remaining = reactions.filter((r) => r.users.length > 0);
+    message.reactions = remaining.length > 0 ? remaining : undefined;
+    return true;
+};
+
+// Sends the current state of a changed message to everyone who got it.
+const sendUpdate = (message: ChatMessage) => {
+    const update = JSON.stringify({ messageType: 'update', data: message });
+    if (message.to) {
+        users.filter((u) => u.nick === message.to || u.nick === message.from).forEach((u) => send(u.ws, update));
+    } else {
+        sendToRoom(message.room as string, update);
+    }
+};
+
+const remember = (room: string, message: ChatMessage) => {
+    const messages = history.get(room) || [];
+    messages.push(message);
+    history.set(room, messages.slice(-HISTORY_LIMIT));
+};
+
+// The page of messages right before `before` (or the latest ones), oldest first.
+const historyMessage = (room: string, before?: number) => {
+    const messages = (history.get(room) || []).filter((m) => before === undefined || m.id < before);
+    const page = messages.slice(-HISTORY_PAGE);
+    return JSON.stringify({
+        messageType: 'history',
+        room,
+        messages: page,
+        hasMore: messages.length > page.length,
+    });
+};
+
+const sendToRoom = (room: string, data: any) => {
+    users.filter((u) => u.rooms.has(room)).forEach((u) => send(u.ws, data));
+};
+
+const send = (ws: WebSocket, data: any) => {
+    if (ws.readyState === WebSocket.OPEN) {
+        ws.send(data);
     }
-}, 5000);
+};
 
 const broadcast = (data: any) => {
     wss.clients.forEach((client) => {
@@ -66,4 +840,4 @@ const broadcast = (data: any) => {
             client.send(data);
         }
     });
-};
\ No newline at end of file
+};
@@ -0,0 +1,150 @@
+//! Avatar images for users, drawn in the browser from their avatar seed.
+//!
+//! By default every avatar is an identicon generated here, so no username leaves the app. With
+//! the `dicebear` feature they are fetched from api.dicebear.com instead, in the style users
+//! picked in their profile.
+
+use crate::protocol::Profile;
+
+/// Whether users get to pick one of [`crate::protocol::AVATAR_STYLES`].
+pub const PICKS_STYLE: bool = cfg!(feature = "dicebear");
+
+/// Cells per side of an identicon.
+const GRID: usize = 5;
+
+/// Image URL of the avatar of `nick`.
+pub fn url(profile: &Profile, nick: &str) -> String {
+    let seed = if profile.avatar_seed.is_empty() {
+        nick
+    } else {
+        &profile.avatar_seed
+    };
+    source(profile, seed)
+}
+
+#[cfg(feature = "dicebear")]
+fn source(profile: &Profile, seed: &str) -> String {
+    use crate::protocol::AVATAR_STYLES;
+
+    let style = if AVATAR_STYLES.contains(&profile.avatar_style.as_str()) {
+        &profile.avatar_style
+    } else {
+        AVATAR_STYLES[0]
+    };
+    format!(
+        "https://api.dicebear.com/7.x/{}/svg?seed={}",
+        style,
+        percent_encode(seed)
+    )
+}
+
+#[cfg(not(feature = "dicebear"))]
+fn source(_profile: &Profile, seed: &str) -> String {
+    format!("data:image/svg+xml,{}", percent_encode(&identicon(seed)))
+}
+
+/// A symmetric pattern of cells in a colour picked from the hash of `seed`, as an SVG document.
+#[cfg_attr(feature = "dicebear", allow(dead_code))]
+fn identicon(seed: &str) -> String {
+    let hash = fnv1a(seed.as_bytes());
+    let hue = hash % 360;
+    let mut shapes = String::new();
+    for (y, row) in cells(hash).iter().enumerate() {
+        for (x, filled) in row.iter().enumerate() {
+            if *filled {
+                shapes.push_str(&format!("<rect x='{}' y='{}' width='1' height='1'/>", x, y));
+            }
+        }
+    }
+    format!(
+        "<svg xmlns='http://www.w3.org/2000/svg' viewBox='-1 -1 {size} {size}' shape-rendering='crispEdges'>\
+         <rect x='-1' y='-1' width='{size}' height='{size}' fill='hsl({hue},45%,90%)'/>\
+         <g fill='hsl({hue},55%,45%)'>{shapes}</g></svg>",
+        size = GRID + 2,
+        hue = hue,
+        shapes = shapes
+    )
+}
+
+/// The left half (and middle column) come from the hash; the right half mirrors it.
+#[cfg_attr(feature = "dicebear", allow(dead_code))]
+fn cells(hash: u64) -> [[bool; GRID]; GRID] {
+    let mut cells = [[false; GRID]; GRID];
+    let half = GRID.div_ceil(2);
+    for (y, row) in cells.iter_mut().enumerate() {
+        for x in 0..half {
+            // The low bits went into the hue.
+            let filled = hash >> (16 + y * half + x) & 1 == 1;
+            row[x] = filled;
+            row[GRID - 1 - x] = filled;
+        }
+    }
+    cells
+}
+
+/// 64-bit FNV-1a: tiny, stable across builds and spreads similar names apart.
+#[cfg_attr(feature = "dicebear", allow(dead_code))]
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Percent-encodes everything but unreserved characters.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identicons_are_deterministic_and_symmetric() {
+        assert_eq!(identicon("alice"), identicon("alice"));
+        assert_ne!(identicon("alice"), identicon("bob"));
+        for row in cells(fnv1a(b"alice")) {
+            assert!(row.iter().eq(row.iter().rev()));
+        }
+    }
+
+    #[test]
+    fn seeds_fall_back_to_the_nick() {
+        let chosen = Profile {
+            avatar_seed: "a b".into(),
+            ..Profile::default()
+        };
+        assert_eq!(url(&Profile::default(), "bob"), source(&Profile::default(), "bob"));
+        assert_eq!(url(&chosen, "bob"), source(&chosen, "a b"));
+    }
+
+    #[cfg(feature = "dicebear")]
+    #[test]
+    fn dicebear_urls_encode_the_seed() {
+        let profile = Profile {
+            avatar_seed: "a b".into(),
+            avatar_style: "unknown".into(),
+            ..Profile::default()
+        };
+        assert_eq!(
+            url(&profile, "bob"),
+            "https://api.dicebear.com/7.x/adventurer-neutral/svg?seed=a%20b"
+        );
+    }
+
+    #[cfg(not(feature = "dicebear"))]
+    #[test]
+    fn avatars_are_data_uris() {
+        let url = url(&Profile::default(), "bob");
+        assert!(url.starts_with("data:image/svg+xml,%3Csvg"));
+        assert!(!url.contains(' ') && !url.contains('#'));
+    }
+}
@@ -1,129 +1,838 @@
-use serde::{Deserialize, Serialize};
-use web_sys::HtmlInputElement;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
+
+use gloo_timers::callback::{Interval, Timeout};
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{HtmlInputElement, HtmlTextAreaElement};
 use yew::prelude::*;
 use yew_agent::{Bridge, Bridged};
+use yew_router::prelude::*;
 
-use crate::{services::{event_bus::EventBus, websocket::WebsocketService}, User};
+use crate::{
+    components::diagnostics::{Diagnostics, DroppedFrame},
+    protocol::{
+        self, ClientMessage, DecodeError, LinkPreview, MessageData, Presence, Profile,
+        RoomInfo, ServerMessage, DEFAULT_ROOM, MAX_MESSAGE_LEN,
+    },
+    services::{
+        event_bus::{EventBus, Request},
+        storage,
+        websocket::ConnectionState,
+    },
+    avatar, history, markdown,
+    media::{self, Media},
+    time, Config, Route, Socket, User,
+};
 
+#[allow(clippy::enum_variant_names)]
 pub enum Msg {
     HandleMsg(String),
+    ConnectionState(ConnectionState),
     SubmitMessage,
+    CreateRoom,
+    LeaveRoom,
+    LoadOlder,
+    OpenDirect(String),
+    Logout,
+    Tick,
+    SendTimedOut(String),
+    RetrySend(String),
+    StartEdit(u64),
+    CancelEdit,
+    DeleteMessage(u64),
+    React(u64, String),
+    Reply(u64),
+    CancelReply,
+    OpenThread(u64),
+    CloseThread,
+    Typed,
+    StopTyping,
+    Active,
+    SetPresence(Presence),
+    ShowProfile(String),
+    HideProfile,
+    ToggleLinkPreviews,
 }
 
-#[derive(Deserialize)]
-struct MessageData {
-    from: String,
-    message: String,
+/// What the message list shows.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Conversation {
+    /// A room, joined when it isn't already.
+    Room(String),
+    /// The private thread with another user.
+    Direct(String),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MsgTypes {
-    Users,
-    Register,
-    Message,
+#[derive(Properties, PartialEq)]
+pub struct ChatProps {
+    pub conversation: Conversation,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct WebSocketMessage {
-    message_type: MsgTypes,
-    data_array: Option<Vec<String>>,
-    data: Option<String>,
-}
+/// Consecutive messages from the same sender closer than this are grouped together.
+const GROUP_WINDOW_MS: u64 = 5 * 60 * 1000;
+/// How often relative timestamps ("5 min ago") are refreshed.
+const CLOCK_TICK_MS: u32 = 30 * 1000;
+
+/// How long a message may go without its echo before it is shown as failed.
+const SEND_TIMEOUT_MS: u32 = 10 * 1000;
+
+/// While typing goes on, typing-start frames are repeated at most this often. The server
+/// expires them a few seconds after the last one.
+const TYPING_THROTTLE_MS: u64 = 3 * 1000;
+/// We count as having stopped typing after this long without input.
+const TYPING_IDLE_MS: u32 = 5 * 1000;
+
+/// Without input for this long, or with the page hidden, we show as away.
+const AWAY_AFTER_MS: u64 = 5 * 60 * 1000;
+
+/// Quoted replies show this many characters of the message they reply to.
+const REPLY_PREVIEW: usize = 80;
+
+/// Offered in the reaction picker; reactions others used show up whatever they are.
+const REACTION_EMOJIS: &[&str] = &["👍", "❤️", "😂", "🎉", "😮", "😢"];
+
+/// At most this many images or videos are shown under a message.
+const MAX_INLINE_MEDIA: usize = 4;
+/// Pages that had no preview are asked about again after this long, as the server may just
+/// not have reached them. It forgets failed fetches after as long.
+const PREVIEW_RETRY_MS: u64 = 60 * 1000;
+
+/// How many dropped frames the diagnostics panel keeps around.
+const RECENT_DROPS: usize = 20;
+/// Dropped frames are shown truncated to this many characters.
+const DROPPED_FRAME_PREVIEW: usize = 200;
+
+/// The text of a message and the addresses it links to.
+type ParsedLinks = (String, Rc<[String]>);
 
 #[derive(Clone)]
 struct UserProfile {
     name: String,
     avatar: String,
+    status: Presence,
+    /// When an offline user left, if the server saw it.
+    last_seen: Option<u64>,
+    profile: Profile,
+}
+
+/// A message of ours the server hasn't echoed yet.
+struct Outgoing {
+    nonce: String,
+    frame: ClientMessage,
+    /// Runs while the frame is on the wire; `None` while waiting for a connection or failed.
+    timeout: Option<Timeout>,
+    failed: bool,
+    /// Why the server refused it, if it did.
+    refused: Option<String>,
+}
+
+/// A link preview asked for.
+enum Preview {
+    Requested,
+    Loaded(LinkPreview),
+    /// The server had none to give, at this time.
+    Missing(u64),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Delivery {
+    Pending,
+    Failed,
+    Sent,
 }
 
 pub struct Chat {
     users: Vec<UserProfile>,
+    rooms: Vec<RoomInfo>,
+    /// Rooms this client is a member of, joined again whenever the connection comes back.
+    joined: BTreeSet<String>,
     chat_input: NodeRef,
-    wss: WebsocketService,
-    messages: Vec<MessageData>,
+    room_input: NodeRef,
+    wss: Socket,
+    config: Config,
+    messages: HashMap<String, Vec<MessageData>>,
+    /// Rooms whose server-side history goes further back than what has been loaded.
+    has_more: HashMap<String, bool>,
+    /// Rooms with an older page of history requested but not received yet.
+    loading_history: HashSet<String>,
+    /// Direct message threads keyed by the other user's name.
+    directs: HashMap<String, Vec<MessageData>>,
+    /// Direct messages received while their thread wasn't open.
+    unread: HashMap<String, usize>,
+    /// Our messages without an echo yet, oldest first so they are resent in order.
+    outbox: Vec<Outgoing>,
+    /// Id of our message whose text is in the input bar for editing.
+    editing: Option<u64>,
+    /// Id of the message the next one we send replies to.
+    replying_to: Option<u64>,
+    /// Id of the first message of the thread shown in the side panel.
+    thread: Option<u64>,
+    /// Others composing a message right now, and where.
+    typing: Vec<(Conversation, String)>,
+    /// Where we last said we are typing, and when.
+    typing_in: Option<(Conversation, u64)>,
+    typing_idle: Option<Timeout>,
+    /// How far everyone has read, for the conversations the server has told us about.
+    reads: HashMap<Conversation, BTreeMap<String, u64>>,
+    /// The presence we picked, and the one the others were last told about.
+    presence: Presence,
+    announced: Presence,
+    /// Last mouse or keyboard input, for going away when idle.
+    last_active: u64,
+    /// The user whose profile card is shown.
+    profile_card: Option<String>,
+    /// Whether links get a preview card. Off unless the user turns it on, as the server then
+    /// fetches every linked page.
+    link_previews: bool,
+    /// Previews asked for, by URL.
+    previews: HashMap<String, Preview>,
+    /// Addresses linked from each message by its id, or its nonce until it has one, along
+    /// with the text they were found in, so messages aren't parsed again on every render.
+    links: RefCell<HashMap<String, ParsedLinks>>,
+    /// Where we had read up to in the open conversation when it was opened; newer messages
+    /// from others are marked as new.
+    unread_after: Option<u64>,
     current_user: String,
+    connection: ConnectionState,
+    dropped_frames: usize,
+    /// Frames of a type this client doesn't know, which newer servers may send.
+    ignored_frames: usize,
+    recent_drops: Vec<DroppedFrame>,
+    _clock: Interval,
+    /// Listens for the tab being hidden or shown, to go away or come back right away.
+    visibility_listener: Closure<dyn Fn()>,
     _producer: Box<dyn Bridge<EventBus>>,
 }
 
 impl Component for Chat {
     type Message = Msg;
-    type Properties = ();
+    type Properties = ChatProps;
 
     fn create(ctx: &Context<Self>) -> Self {
         let (user, _) = ctx
             .link()
             .context::<User>(Callback::noop())
             .expect("Context to be set");
+        let (wss, _) = ctx
+            .link()
+            .context::<Socket>(Callback::noop())
+            .expect("Socket context to be set");
+        let (config, _) = ctx
+            .link()
+            .context::<Config>(Callback::noop())
+            .expect("Config context to be set");
 
-        let wss = WebsocketService::new();
         let username = user.username.borrow().clone();
 
-        let message = WebSocketMessage {
-            message_type: MsgTypes::Register,
-            data: Some(username.to_string()),
-            data_array: None,
-        };
-
         log::debug!("Create function");
 
-        if let Ok(_) = wss.tx.clone().try_send(serde_json::to_string(&message).unwrap()) {
-            log::debug!("Message sent successfully!");
-        }
+        // Show what we had last time right away; backfill from the server is merged in by id.
+        let cache = storage::load_messages(&username);
+        let has_more = cache
+            .rooms
+            .iter()
+            .filter(|(_, messages)| history::oldest_id(messages).is_some())
+            .map(|(room, _)| (room.clone(), true))
+            .collect();
 
-        Self {
+        let mut chat = Self {
             users: vec![],
-            messages: vec![],
+            rooms: vec![],
+            joined: BTreeSet::from([DEFAULT_ROOM.to_string()]),
+            messages: cache.rooms,
+            has_more,
+            loading_history: HashSet::new(),
+            directs: cache.directs,
+            unread: HashMap::new(),
+            outbox: vec![],
+            editing: None,
+            replying_to: None,
+            thread: None,
+            typing: vec![],
+            typing_in: None,
+            typing_idle: None,
+            reads: HashMap::new(),
+            unread_after: None,
+            presence: storage::load_presence().unwrap_or(Presence::Online),
+            announced: Presence::Online,
+            last_active: time::now(),
+            profile_card: None,
+            link_previews: storage::load_link_previews().unwrap_or(false),
+            previews: HashMap::new(),
+            links: RefCell::default(),
             chat_input: NodeRef::default(),
+            room_input: NodeRef::default(),
+            connection: wss.state(),
             wss,
+            config,
             current_user: username,
-            _producer: EventBus::bridge(ctx.link().callback(Msg::HandleMsg)),
+            dropped_frames: 0,
+            ignored_frames: 0,
+            recent_drops: vec![],
+            _clock: {
+                let link = ctx.link().clone();
+                Interval::new(CLOCK_TICK_MS, move || link.send_message(Msg::Tick))
+            },
+            visibility_listener: {
+                let link = ctx.link().clone();
+                // Showing the tab again counts as activity; hiding it makes us idle.
+                Closure::wrap(Box::new(move || link.send_message(Msg::Active)) as Box<dyn Fn()>)
+            },
+            _producer: EventBus::bridge(ctx.link().callback(|req| match req {
+                Request::EventBusMsg(s) => Msg::HandleMsg(s),
+                Request::ConnectionState(state) => Msg::ConnectionState(state),
+            })),
+        };
+        // Registering again is harmless and makes the server send the user list and history
+        // we missed while the login page was showing.
+        chat.send(ClientMessage::Register {
+            data: chat.current_user.clone(),
+        });
+        if let Some(document) = web_sys::window().and_then(|w| w.document()) {
+            let listener = chat.visibility_listener.as_ref().unchecked_ref();
+            if let Err(e) = document.add_event_listener_with_callback("visibilitychange", listener) {
+                log::warn!("Could not watch page visibility: {:?}", e);
+            }
         }
+        chat.update_presence();
+        chat.open(&ctx.props().conversation);
+        chat
+    }
+
+    fn destroy(&mut self, _ctx: &Context<Self>) {
+        if let Some(document) = web_sys::window().and_then(|w| w.document()) {
+            let listener = self.visibility_listener.as_ref().unchecked_ref();
+            let _ = document.remove_event_listener_with_callback("visibilitychange", listener);
+        }
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        self.stop_typing();
+        self.cancel_edit();
+        self.replying_to = None;
+        self.thread = None;
+        self.profile_card = None;
+        self.open(&ctx.props().conversation);
+        true
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             Msg::HandleMsg(s) => {
-                let msg: WebSocketMessage = serde_json::from_str(&s).unwrap();
-                match msg.message_type {
-                    MsgTypes::Users => {
-                        let users_from_message = msg.data_array.unwrap_or_default();
-                        self.users = users_from_message
+                let msg = match ServerMessage::decode(&s) {
+                    Ok(msg) => msg,
+                    Err(error) => {
+                        self.drop_frame(error, s);
+                        return true;
+                    }
+                };
+                match msg {
+                    ServerMessage::AuthOk { .. } | ServerMessage::RegisterAck { .. } => false,
+                    ServerMessage::AuthRejected { reason }
+                    | ServerMessage::RegisterRejected { reason } => {
+                        // Most likely the session token expired while we were away.
+                        log::warn!("Session rejected: {}", reason);
+                        storage::forget_session();
+                        self.end_session(ctx);
+                        false
+                    }
+                    ServerMessage::Users { data_array } => {
+                        let mut users: Vec<UserProfile> = data_array
                             .iter()
-                            .map(|u| UserProfile {
-                                name: u.into(),
-                                avatar: format!(
-                                    "https://api.dicebear.com/7.x/adventurer-neutral/svg?seed={}",
-                                    u
-                                )
-                                .into(),
+                            .map(|u| {
+                                let known = self.users.iter().find(|p| &p.name == u);
+                                let profile = known.map(|p| p.profile.clone()).unwrap_or_default();
+                                UserProfile {
+                                    name: u.into(),
+                                    avatar: avatar::url(&profile, u),
+                                    status: known
+                                        .map(|p| p.status)
+                                        .filter(|s| *s != Presence::Offline)
+                                        .unwrap_or(Presence::Online),
+                                    last_seen: None,
+                                    profile,
+                                }
                             })
                             .collect();
-                        return true;
+                        // Whoever left stays listed as offline.
+                        users.extend(
+                            self.users
+                                .drain(..)
+                                .filter(|p| !data_array.contains(&p.name))
+                                .map(|p| UserProfile {
+                                    status: Presence::Offline,
+                                    ..p
+                                }),
+                        );
+                        self.users = users;
+                        true
                     }
-                    MsgTypes::Message => {
-                        let message_data: MessageData = serde_json::from_str(&msg.data.unwrap()).unwrap();
-                        self.messages.push(message_data);
-                        return true;
+                    ServerMessage::Presence {
+                        data,
+                        status,
+                        last_seen,
+                    } => {
+                        match self.users.iter_mut().find(|u| u.name == data) {
+                            Some(user) => {
+                                user.status = status;
+                                user.last_seen = last_seen;
+                            }
+                            None => self.users.push(UserProfile {
+                                avatar: avatar::url(&Profile::default(), &data),
+                                name: data,
+                                status,
+                                last_seen,
+                                profile: Profile::default(),
+                            }),
+                        }
+                        true
+                    }
+                    ServerMessage::Profile { data, profile } => {
+                        if data == self.current_user {
+                            storage::save_profile(&profile);
+                        }
+                        let avatar = avatar::url(&profile, &data);
+                        match self.users.iter_mut().find(|u| u.name == data) {
+                            Some(user) => {
+                                user.avatar = avatar;
+                                user.profile = profile;
+                            }
+                            // Everyone online is in the user list already.
+                            None => self.users.push(UserProfile {
+                                name: data,
+                                avatar,
+                                status: Presence::Offline,
+                                last_seen: None,
+                                profile,
+                            }),
+                        }
+                        true
+                    }
+                    ServerMessage::Rooms { rooms } => {
+                        self.rooms = rooms;
+                        true
+                    }
+                    ServerMessage::Message { data } => {
+                        self.confirm(std::slice::from_ref(&data));
+                        let buffer = self.messages.entry(data.room.clone()).or_default();
+                        let added = history::merge(buffer, vec![data]) > 0;
+                        if added {
+                            self.persist();
+                        }
+                        added
                     }
-                    _ => {
-                        return false;
+                    ServerMessage::History {
+                        room,
+                        messages,
+                        has_more,
+                    } => {
+                        self.loading_history.remove(&room);
+                        // The echo of a message may only come back in the history after a reconnect.
+                        self.confirm(&messages);
+                        let buffer = self.messages.entry(room.clone()).or_default();
+                        // A page re-sent after reconnecting doesn't tell whether the older
+                        // pages we already have are the last ones.
+                        let oldest = history::oldest_id(buffer);
+                        if oldest.is_none() || history::oldest_id(&messages) <= oldest {
+                            self.has_more.insert(room, has_more);
+                        }
+                        if history::merge(buffer, messages) > 0 {
+                            self.persist();
+                        }
+                        true
+                    }
+                    ServerMessage::Direct { data } => {
+                        self.confirm(std::slice::from_ref(&data));
+                        let peer = if data.from == self.current_user {
+                            data.to.clone().unwrap_or_default()
+                        } else {
+                            data.from.clone()
+                        };
+                        let from_peer = data.from != self.current_user;
+                        let added = history::merge(self.directs.entry(peer.clone()).or_default(), vec![data]);
+                        // Copies redelivered after a reconnect are nothing new.
+                        if from_peer && added > 0 && ctx.props().conversation != Conversation::Direct(peer.clone()) {
+                            *self.unread.entry(peer.clone()).or_default() += 1;
+                        }
+                        // Read positions in threads started since we registered arrive as
+                        // they move; until then there are none.
+                        self.reads.entry(Conversation::Direct(peer)).or_default();
+                        self.persist();
+                        true
                     }
+                    ServerMessage::DirectRejected { nonce, reason, .. }
+                    | ServerMessage::MessageRejected { nonce, reason } => {
+                        let outgoing = self
+                            .outbox
+                            .iter_mut()
+                            .find(|o| nonce.as_ref() == Some(&o.nonce));
+                        match outgoing {
+                            Some(outgoing) => {
+                                log::debug!("Message {} refused: {}", outgoing.nonce, reason);
+                                outgoing.timeout = None;
+                                outgoing.failed = true;
+                                outgoing.refused = Some(reason);
+                                true
+                            }
+                            None => false,
+                        }
+                    }
+                    ServerMessage::Reads {
+                        room,
+                        peer,
+                        positions,
+                    } => {
+                        let conversation = match (room, peer) {
+                            (Some(room), _) => Conversation::Room(room),
+                            (None, Some(peer)) => Conversation::Direct(peer),
+                            (None, None) => return false,
+                        };
+                        if conversation == ctx.props().conversation && self.unread_after.is_none() {
+                            self.unread_after = positions.get(&self.current_user).copied();
+                        }
+                        // Ours may have moved on locally before the server heard about it.
+                        let known = self.reads.entry(conversation).or_default();
+                        for (name, id) in positions {
+                            let position = known.entry(name).or_default();
+                            *position = (*position).max(id);
+                        }
+                        true
+                    }
+                    ServerMessage::Typing { data, room, active } => {
+                        // Our own typing in another window isn't news.
+                        if data == self.current_user {
+                            return false;
+                        }
+                        let conversation = match room {
+                            Some(room) => Conversation::Room(room),
+                            None => Conversation::Direct(data.clone()),
+                        };
+                        let entry = (conversation, data);
+                        let known = self.typing.contains(&entry);
+                        if active && !known {
+                            self.typing.push(entry);
+                        } else if !active && known {
+                            self.typing.retain(|e| *e != entry);
+                        }
+                        active != known
+                    }
+                    ServerMessage::Preview { url, preview } => {
+                        let preview = match preview {
+                            Some(preview) => Preview::Loaded(preview),
+                            None => Preview::Missing(time::now()),
+                        };
+                        self.previews.insert(url, preview);
+                        self.link_previews
+                    }
+                    ServerMessage::Update { data } => {
+                        let buffer = match &data.to {
+                            Some(to) if data.from == self.current_user => self.directs.get_mut(to),
+                            Some(_) => self.directs.get_mut(&data.from),
+                            None => self.messages.get_mut(&data.room),
+                        };
+                        let updated = buffer.is_some_and(|buffer| history::update(buffer, data));
+                        if updated {
+                            self.persist();
+                        }
+                        updated
+                    }
+                }
+            }
+            Msg::Tick => {
+                self.update_presence();
+                true
+            }
+            Msg::ConnectionState(state) => {
+                self.connection = state;
+                // Whoever was typing will say so again on the new connection, and the read
+                // positions come again after registering.
+                self.typing.clear();
+                self.reads.clear();
+                // Previews whose answer was lost with the connection are asked for again.
+                self.previews.retain(|_, preview| !matches!(preview, Preview::Requested));
+                if state == ConnectionState::Open {
+                    // Every new connection starts out online.
+                    self.announced = Presence::Online;
+                    self.update_presence();
+                    // The socket only registers us again, so restore room memberships ourselves.
+                    for room in self.joined.iter().filter(|room| *room != DEFAULT_ROOM) {
+                        self.send(ClientMessage::Join { room: room.clone() });
+                    }
+                    // Messages written while offline, or whose echo was lost with the
+                    // connection. The server only echoes those it already has again.
+                    let queued: Vec<String> = self
+                        .outbox
+                        .iter()
+                        .filter(|o| !o.failed)
+                        .map(|o| o.nonce.clone())
+                        .collect();
+                    for nonce in queued {
+                        self.transmit(ctx, &nonce);
+                    }
+                } else {
+                    // Time spent offline doesn't count towards the send timeout.
+                    for outgoing in self.outbox.iter_mut() {
+                        outgoing.timeout = None;
+                    }
+                }
+                true
+            }
+            Msg::SendTimedOut(nonce) => {
+                match self.outbox.iter_mut().find(|o| o.nonce == nonce) {
+                    Some(outgoing) if outgoing.timeout.is_some() => {
+                        outgoing.timeout = None;
+                        outgoing.failed = true;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Msg::RetrySend(nonce) => {
+                self.transmit(ctx, &nonce);
+                true
+            }
+            Msg::StartEdit(id) => {
+                let text = self
+                    .conversation_messages(&ctx.props().conversation)
+                    .and_then(|messages| messages.iter().find(|m| m.id == id))
+                    .map(|m| m.message.clone());
+                match (text, self.chat_input.cast::<HtmlTextAreaElement>()) {
+                    (Some(text), Some(input)) => {
+                        input.set_value(&text);
+                        let _ = input.focus();
+                        self.editing = Some(id);
+                        self.replying_to = None;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Msg::CancelEdit => {
+                self.cancel_edit();
+                true
+            }
+            Msg::DeleteMessage(id) => {
+                let confirmed = web_sys::window()
+                    .and_then(|w| w.confirm_with_message("Delete this message?").ok())
+                    .unwrap_or_default();
+                if confirmed {
+                    if self.editing == Some(id) {
+                        self.cancel_edit();
+                    }
+                    self.send(ClientMessage::Delete { id });
+                }
+                confirmed
+            }
+            Msg::React(id, emoji) => {
+                self.send(ClientMessage::React { id, emoji });
+                false
+            }
+            Msg::Reply(id) => {
+                self.cancel_edit();
+                self.replying_to = Some(id);
+                if let Some(input) = self.chat_input.cast::<HtmlTextAreaElement>() {
+                    let _ = input.focus();
+                }
+                true
+            }
+            Msg::CancelReply => {
+                self.replying_to = None;
+                true
+            }
+            Msg::OpenThread(id) => {
+                let messages = self.conversation_messages(&ctx.props().conversation);
+                self.thread = Some(messages.map_or(id, |m| history::Threads::new(m).root(id)));
+                true
+            }
+            Msg::CloseThread => {
+                self.thread = None;
+                true
+            }
+            Msg::Typed => {
+                let empty = self
+                    .chat_input
+                    .cast::<HtmlTextAreaElement>()
+                    .is_none_or(|input| input.value().is_empty());
+                if empty {
+                    self.stop_typing();
+                    return false;
                 }
+                let conversation = ctx.props().conversation.clone();
+                let now = time::now();
+                let announced = self.typing_in.as_ref().is_some_and(|(c, since)| {
+                    *c == conversation && now.saturating_sub(*since) < TYPING_THROTTLE_MS
+                });
+                if !announced {
+                    self.send(typing_frame(&conversation, true));
+                    self.typing_in = Some((conversation, now));
+                }
+                let link = ctx.link().clone();
+                self.typing_idle = Some(Timeout::new(TYPING_IDLE_MS, move || {
+                    link.send_message(Msg::StopTyping)
+                }));
+                false
+            }
+            Msg::StopTyping => {
+                self.stop_typing();
+                false
+            }
+            Msg::Active => {
+                self.last_active = time::now();
+                self.update_presence();
+                false
+            }
+            Msg::ShowProfile(name) => {
+                self.profile_card = Some(name);
+                true
+            }
+            Msg::HideProfile => {
+                self.profile_card = None;
+                true
+            }
+            Msg::ToggleLinkPreviews => {
+                self.link_previews = !self.link_previews;
+                storage::save_link_previews(self.link_previews);
+                true
+            }
+            Msg::SetPresence(presence) => {
+                self.presence = presence;
+                storage::save_presence(presence);
+                self.last_active = time::now();
+                self.update_presence();
+                true
             }
             Msg::SubmitMessage => {
-                let input = self.chat_input.cast::<HtmlInputElement>();
+                let input = match self.chat_input.cast::<HtmlTextAreaElement>() {
+                    Some(input) => input,
+                    None => return false,
+                };
+                if input.value().trim().is_empty() {
+                    return false;
+                }
+                self.stop_typing();
+                // Edits aren't shown until the server has accepted them.
+                if let Some(id) = self.editing.take() {
+                    self.send(ClientMessage::Edit {
+                        id,
+                        data: input.value(),
+                    });
+                    input.set_value("");
+                    return true;
+                }
+                // Shown right away and replaced by the server's echo, recognised by its nonce.
+                let nonce = new_nonce();
+                let parent = self.replying_to.take();
+                let pending = MessageData {
+                    id: 0,
+                    from: self.current_user.clone(),
+                    message: input.value(),
+                    time: time::now(),
+                    room: DEFAULT_ROOM.to_string(),
+                    to: None,
+                    nonce: Some(nonce.clone()),
+                    edited: None,
+                    deleted: false,
+                    reactions: vec![],
+                    parent,
+                };
+                let frame = match &ctx.props().conversation {
+                    Conversation::Room(room) => {
+                        let buffer = self.messages.entry(room.clone()).or_default();
+                        history::merge(
+                            buffer,
+                            vec![MessageData {
+                                room: room.clone(),
+                                ..pending
+                            }],
+                        );
+                        ClientMessage::Message {
+                            data: input.value(),
+                            room: room.clone(),
+                            nonce: nonce.clone(),
+                            parent,
+                        }
+                    }
+                    Conversation::Direct(to) => {
+                        let buffer = self.directs.entry(to.clone()).or_default();
+                        history::merge(
+                            buffer,
+                            vec![MessageData {
+                                to: Some(to.clone()),
+                                ..pending
+                            }],
+                        );
+                        ClientMessage::Direct {
+                            data: input.value(),
+                            to: to.clone(),
+                            nonce: nonce.clone(),
+                            parent,
+                        }
+                    }
+                };
+                self.outbox.push(Outgoing {
+                    nonce: nonce.clone(),
+                    frame,
+                    timeout: None,
+                    failed: false,
+                    refused: None,
+                });
+                self.transmit(ctx, &nonce);
+                input.set_value("");
+                true
+            }
+            Msg::CreateRoom => {
+                let input = self.room_input.cast::<HtmlInputElement>();
                 if let Some(input) = input {
-                    let message = WebSocketMessage {
-                        message_type: MsgTypes::Message,
-                        data: Some(input.value()),
-                        data_array: None,
-                    };
-                    if let Err(e) = self.wss.tx.clone().try_send(serde_json::to_string(&message).unwrap()) {
-                        log::debug!("Error sending to channel: {:?}", e);
+                    match protocol::room_id(&input.value()) {
+                        Some(id) => {
+                            input.set_value("");
+                            if let Some(history) = ctx.link().history() {
+                                history.push(room_route(&id));
+                            }
+                        }
+                        None => log::debug!("Invalid room name {:?}", input.value()),
                     }
-                    input.set_value("");
+                }
+                false
+            }
+            Msg::LeaveRoom => {
+                if let Conversation::Room(room) = &ctx.props().conversation {
+                    if room != DEFAULT_ROOM && self.joined.remove(room) {
+                        self.send(ClientMessage::Leave { room: room.clone() });
+                        self.messages.remove(room);
+                        self.persist();
+                        if let Some(history) = ctx.link().history() {
+                            history.push(Route::Chat);
+                        }
+                    }
+                }
+                false
+            }
+            Msg::LoadOlder => {
+                if let Conversation::Room(room) = &ctx.props().conversation {
+                    let oldest = self.messages.get(room).and_then(|m| history::oldest_id(m));
+                    if let Some(before) = oldest {
+                        if self.loading_history.insert(room.clone()) {
+                            self.send(ClientMessage::HistoryRequest {
+                                room: room.clone(),
+                                before,
+                            });
+                        }
+                    }
+                }
+                true
+            }
+            Msg::Logout => {
+                self.send(ClientMessage::Unregister);
+                storage::clear_session(&self.current_user);
+                self.end_session(ctx);
+                false
+            }
+            Msg::OpenDirect(user) => {
+                self.profile_card = None;
+                if let Some(history) = ctx.link().history() {
+                    history.push(Route::Direct { user });
                 }
                 false
             }
@@ -132,13 +841,44 @@ impl Component for Chat {
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let submit = ctx.link().callback(|_| Msg::SubmitMessage);
+        let leave = ctx.link().callback(|_| Msg::LeaveRoom);
+        let logout = ctx.link().callback(|_| Msg::Logout);
+        let toggle_previews = ctx.link().callback(|_| Msg::ToggleLinkPreviews);
+        let cancel_edit = ctx.link().callback(|_| Msg::CancelEdit);
+        let cancel_reply = ctx.link().callback(|_| Msg::CancelReply);
+        let typed = ctx.link().callback(|_: InputEvent| Msg::Typed);
+        // Enter sends, Shift+Enter starts a new line.
+        let keydown = ctx.link().batch_callback(|e: KeyboardEvent| {
+            let send = e.key() == "Enter" && !e.shift_key() && !e.is_composing();
+            if send {
+                e.prevent_default();
+            }
+            send.then_some(Msg::SubmitMessage)
+        });
+        let moved = ctx.link().callback(|_: MouseEvent| Msg::Active);
+        let pressed = ctx.link().callback(|_: KeyboardEvent| Msg::Active);
+        let now = time::now();
+        let conversation = &ctx.props().conversation;
+        let messages = self.conversation_messages(conversation);
+        let title = match conversation {
+            Conversation::Room(room) => format!("💬 #{}", room),
+            Conversation::Direct(user) => format!("✉️ @{}", user),
+        };
         html! {
-            <div class="flex w-screen bg-gray-900 text-white">
-                <div class="flex-none w-56 h-screen bg-gray-800 border-r border-gray-700">
-                    <div class="text-xl p-3 text-white font-semibold border-b border-gray-700">{"👥 Users"}</div>
+            <div onmousemove={moved} onkeydown={pressed} class="flex w-screen bg-gray-900 text-white">
+                <div class="flex-none w-56 h-screen overflow-auto bg-gray-800 border-r border-gray-700">
+                    <div class="text-xl p-3 text-white font-semibold border-b border-gray-700">{"🗂️ Rooms"}</div>
+                    { self.view_rooms(ctx) }
+                    <div class="text-xl p-3 text-white font-semibold border-b border-t border-gray-700">{"✉️ Direct"}</div>
+                    { self.view_directs(ctx) }
+                    <div class="text-xl p-3 text-white font-semibold border-b border-t border-gray-700">{"👥 Users"}</div>
                     {
-                        self.users.clone().iter().map(|u| {
+                        self.members(conversation).into_iter().map(|u| {
                             let is_current_user = u.name == self.current_user;
+                            let onclick = {
+                                let name = u.name.clone();
+                                ctx.link().callback(move |_| Msg::ShowProfile(name.clone()))
+                            };
                             let user_bg_class = if is_current_user {
                                 "bg-blue-600 border-blue-500"
                             } else {
@@ -146,14 +886,15 @@ impl Component for Chat {
                             };
 
                             html!{
-                                <div class={format!("flex m-3 rounded-lg p-2 border transition-colors duration-200 {}", user_bg_class)}>
-                                    <div>
+                                <div {onclick} class={classes!("flex", "m-3", "rounded-lg", "p-2", "border", "transition-colors", "duration-200", "cursor-pointer", user_bg_class, (u.status == Presence::Offline).then_some("opacity-60"))}>
+                                    <div class="relative flex-none">
                                         <img class="w-12 h-12 rounded-full border-2 border-gray-500" src={u.avatar.clone()} alt="avatar"/>
+                                        <span class={format!("absolute bottom-0 right-0 w-3 h-3 rounded-full border-2 border-gray-800 {}", presence_color(u.status))} title={u.status.label()}></span>
                                     </div>
                                     <div class="flex-grow p-3">
                                         <div class="flex text-sm justify-between">
                                             <div class="font-medium">
-                                                {u.name.clone()}
+                                                {u.profile.display_name(&u.name).to_string()}
                                                 if is_current_user {
                                                     <span class="ml-2 text-xs bg-blue-800 px-2 py-1 rounded-full">{"You"}</span>
                                                 }
@@ -161,80 +902,70 @@ impl Component for Chat {
                                         </div>
                                         <div class="text-xs text-gray-300">
                                             if is_current_user {
-                                                {"That's you!"}
+                                                { self.view_presence_picker(ctx) }
+                                            } else if let (Presence::Offline, Some(last_seen)) = (u.status, u.last_seen) {
+                                                {format!("Last seen {}", last_seen_label(now, last_seen))}
                                             } else {
-                                                {"Online"}
+                                                {u.status.label()}
                                             }
                                         </div>
+                                        if !u.profile.status_text.is_empty() {
+                                            <div class="text-xs italic text-gray-400 truncate">{u.profile.status_text.clone()}</div>
+                                        }
                                     </div>
                                 </div>
                             }
                         }).collect::<Html>()
                     }
+                    <Diagnostics dropped={self.dropped_frames} ignored={self.ignored_frames} recent={self.recent_drops.clone()} />
                 </div>
                 <div class="grow h-screen flex flex-col bg-gray-900">
-                    <div class="w-full h-14 border-b border-gray-700 bg-gray-800">
-                        <div class="text-xl p-3 text-white font-semibold">{"💬 Chat Room"}</div>
-                    </div>
-                    <div class="w-full grow overflow-auto border-b border-gray-700 p-4 bg-gray-900">
-                        {
-                            self.messages.iter().map(|m| {
-                                let user = self.users.iter().find(|u| u.name == m.from);
-                                let is_current_user = m.from == self.current_user;
-
-                                if let Some(user) = user {
-                                    let message_classes = if is_current_user {
-                                        "flex items-end justify-end w-full mb-4"
-                                    } else {
-                                        "flex items-end w-full mb-4"
-                                    };
-
-                                    let bubble_classes = if is_current_user {
-                                        "bg-blue-600 text-white rounded-tl-lg rounded-tr-lg rounded-bl-lg border border-blue-500 max-w-xs lg:max-w-md"
-                                    } else {
-                                        "bg-gray-700 text-white rounded-tl-lg rounded-tr-lg rounded-br-lg border border-gray-600 max-w-xs lg:max-w-md"
-                                    };
-
-                                    html!{
-                                        <div class={message_classes}>
-                                            if !is_current_user {
-                                                <img class="w-8 h-8 rounded-full mr-3 border-2 border-gray-600" src={user.avatar.clone()} alt="avatar"/>
-                                            }
-                                            <div class={bubble_classes}>
-                                                <div class="p-3">
-                                                    <div class="text-sm font-medium mb-1">
-                                                        if is_current_user {
-                                                            {"You"}
-                                                        } else {
-                                                            {m.from.clone()}
-                                                        }
-                                                    </div>
-                                                    <div class="text-sm">
-                                                        if m.message.ends_with(".gif") {
-                                                            <img class="mt-2 rounded max-w-full" src={m.message.clone()}/>
-                                                        } else {
-                                                            {m.message.clone()}
-                                                        }
-                                                    </div>
-                                                </div>
-                                            </div>
-                                            if is_current_user {
-                                                <img class="w-8 h-8 rounded-full ml-3 border-2 border-blue-500" src={user.avatar.clone()} alt="avatar"/>
-                                            }
-                                        </div>
-                                    }
+                    <div class="w-full h-14 flex items-center justify-between border-b border-gray-700 bg-gray-800">
+                        <div class="text-xl p-3 text-white font-semibold">{title}</div>
+                        <div class="flex mr-4">
+                            if matches!(conversation, Conversation::Room(room) if room != DEFAULT_ROOM) {
+                                <button onclick={leave} class="ml-2 px-3 py-1 text-sm rounded-full border border-gray-600 hover:bg-gray-700">
+                                    {"Leave"}
+                                </button>
+                            }
+                            <button onclick={toggle_previews} title="Cards with the title and description of linked pages, which the server fetches for you" class="ml-2 px-3 py-1 text-sm rounded-full border border-gray-600 hover:bg-gray-700">
+                                if self.link_previews {
+                                    {"🔗 Previews on"}
                                 } else {
-                                    html!{}
+                                    {"🔗 Previews off"}
                                 }
-                            }).collect::<Html>()
-                        }
+                            </button>
+                            <button onclick={logout} class="ml-2 px-3 py-1 text-sm rounded-full border border-gray-600 hover:bg-gray-700">
+                                {"Log out"}
+                            </button>
+                        </div>
+                    </div>
+                    { self.view_connection_banner() }
+                    <div class="w-full grow overflow-auto border-b border-gray-700 p-4 bg-gray-900">
+                        { self.view_load_older(ctx) }
+                        { self.view_messages(ctx, messages.map(Vec::as_slice).unwrap_or_default()) }
                     </div>
-                    <div class="w-full h-16 flex px-4 items-center bg-gray-800 border-t border-gray-700">
-                        <input
+                    { self.view_typing(conversation) }
+                    if self.editing.is_some() {
+                        <div class="w-full flex justify-between px-7 py-1 text-xs text-gray-300 bg-gray-800 border-t border-gray-700">
+                            <span>{"✏️ Editing message"}</span>
+                            <button onclick={cancel_edit} class="underline hover:text-white">{"Cancel"}</button>
+                        </div>
+                    } else if let Some(parent) = self.replying_to {
+                        <div class="w-full flex justify-between px-7 py-1 text-xs text-gray-300 bg-gray-800 border-t border-gray-700">
+                            <span class="truncate">{format!("↩️ Replying to {}", self.quote(messages.into_iter().flatten().find(|m| m.id == parent)))}</span>
+                            <button onclick={cancel_reply} class="underline hover:text-white">{"Cancel"}</button>
+                        </div>
+                    }
+                    <div class="w-full min-h-16 flex px-4 py-2 items-center bg-gray-800 border-t border-gray-700">
+                        <textarea
                             ref={self.chat_input.clone()}
-                            type="text"
+                            oninput={typed}
+                            onkeydown={keydown}
+                            rows="1"
+                            maxlength={MAX_MESSAGE_LEN.to_string()}
                             placeholder="Type your message..."
-                            class="block w-full py-3 pl-4 mx-3 bg-gray-700 border border-gray-600 rounded-full outline-none focus:border-blue-500 focus:bg-gray-600 text-white placeholder-gray-400 transition-colors duration-200"
+                            class="block w-full h-12 max-h-48 py-3 px-4 mx-3 resize-y bg-gray-700 border border-gray-600 rounded-3xl outline-none focus:border-blue-500 focus:bg-gray-600 text-white placeholder-gray-400 transition-colors duration-200"
                             name="message"
                             required=true
                         />
@@ -249,7 +980,919 @@ impl Component for Chat {
                         </button>
                     </div>
                 </div>
+                if let Some(root) = self.thread {
+                    { self.view_thread(ctx, messages.map(Vec::as_slice).unwrap_or_default(), root) }
+                }
+                if let Some(name) = &self.profile_card {
+                    { self.view_profile_card(ctx, name) }
+                }
             </div>
         }
     }
-}
\ No newline at end of file
+
+    fn rendered(&mut self, ctx: &Context<Self>, _first_render: bool) {
+        self.mark_read(&ctx.props().conversation);
+        self.request_previews(&ctx.props().conversation);
+    }
+}
+
+impl Chat {
+    fn send(&self, message: ClientMessage) {
+        if let Err(e) = self.wss.tx.clone().try_send(message.encode()) {
+            log::debug!("Error sending to channel: {:?}", e);
+        }
+    }
+
+    /// Logs the user out of the app and goes back to the login page.
+    fn end_session(&self, ctx: &Context<Self>) {
+        if let Some((user, _)) = ctx.link().context::<User>(Callback::noop()) {
+            user.username.borrow_mut().clear();
+            user.token.borrow_mut().clear();
+        }
+        if let Some(history) = ctx.link().history() {
+            history.push(Route::Login);
+        }
+    }
+
+    /// Puts the queued message `nonce` on the wire and starts its timeout. While offline it
+    /// stays queued until the connection is back.
+    fn transmit(&mut self, ctx: &Context<Self>, nonce: &str) {
+        let connected = self.connection == ConnectionState::Open;
+        let outgoing = match self.outbox.iter_mut().find(|o| o.nonce == nonce) {
+            Some(outgoing) => outgoing,
+            None => return,
+        };
+        outgoing.failed = false;
+        outgoing.refused = None;
+        outgoing.timeout = None;
+        if !connected {
+            return;
+        }
+        if let Err(e) = self.wss.tx.clone().try_send(outgoing.frame.encode()) {
+            log::debug!("Error sending to channel: {:?}", e);
+            outgoing.failed = true;
+            return;
+        }
+        let link = ctx.link().clone();
+        let nonce = nonce.to_string();
+        outgoing.timeout = Some(Timeout::new(SEND_TIMEOUT_MS, move || {
+            link.send_message(Msg::SendTimedOut(nonce))
+        }));
+    }
+
+    /// Takes messages of ours the server has now stored off the outbox.
+    fn confirm(&mut self, messages: &[MessageData]) {
+        let current_user = &self.current_user;
+        self.outbox.retain(|o| {
+            !messages
+                .iter()
+                .any(|m| m.id != 0 && &m.from == current_user && m.nonce.as_ref() == Some(&o.nonce))
+        });
+    }
+
+    fn delivery(&self, m: &MessageData) -> Delivery {
+        if !m.is_pending() {
+            return Delivery::Sent;
+        }
+        let failed = self
+            .outbox
+            .iter()
+            .any(|o| o.failed && m.nonce.as_ref() == Some(&o.nonce));
+        if failed {
+            Delivery::Failed
+        } else {
+            Delivery::Pending
+        }
+    }
+
+    /// Why the server refused our message `m`, if it did.
+    fn refusal(&self, m: &MessageData) -> Option<&str> {
+        self.outbox
+            .iter()
+            .find(|o| m.nonce.as_ref() == Some(&o.nonce))
+            .and_then(|o| o.refused.as_deref())
+    }
+
+    fn conversation_messages(&self, conversation: &Conversation) -> Option<&Vec<MessageData>> {
+        match conversation {
+            Conversation::Room(room) => self.messages.get(room),
+            Conversation::Direct(user) => self.directs.get(user),
+        }
+    }
+
+    /// Sender and start of the message a reply refers to, if it is loaded.
+    fn quote(&self, message: Option<&MessageData>) -> String {
+        match message {
+            Some(m) if m.deleted => "a deleted message".to_string(),
+            Some(m) => {
+                let mut text: String = m.message.chars().take(REPLY_PREVIEW).collect();
+                if text.len() < m.message.len() {
+                    text.push('…');
+                }
+                format!("{}: {}", m.from, text)
+            }
+            None => "an earlier message".to_string(),
+        }
+    }
+
+    /// Tells the others we are no longer typing, if we told them we were.
+    fn stop_typing(&mut self) {
+        self.typing_idle = None;
+        if let Some((conversation, _)) = self.typing_in.take() {
+            self.send(typing_frame(&conversation, false));
+        }
+    }
+
+    fn cancel_edit(&mut self) {
+        if self.editing.take().is_some() {
+            if let Some(input) = self.chat_input.cast::<HtmlTextAreaElement>() {
+                input.set_value("");
+            }
+        }
+    }
+
+    /// Saves the message buffers after they changed, and forgets the links of messages no
+    /// longer in them: pending ones the echo replaced and deleted ones.
+    fn persist(&self) {
+        storage::save_messages(&self.current_user, &self.messages, &self.directs);
+        let live: HashSet<String> = self
+            .messages
+            .values()
+            .chain(self.directs.values())
+            .flatten()
+            .filter(|m| !m.deleted)
+            .map(links_key)
+            .collect();
+        self.links.borrow_mut().retain(|key, _| live.contains(key));
+    }
+
+    fn open(&mut self, conversation: &Conversation) {
+        self.unread_after = self.read_position(conversation);
+        match conversation {
+            Conversation::Room(room) => self.join(room),
+            Conversation::Direct(user) => {
+                self.unread.remove(user);
+                self.directs.entry(user.clone()).or_default();
+            }
+        }
+    }
+
+    fn read_position(&self, conversation: &Conversation) -> Option<u64> {
+        self.reads.get(conversation)?.get(&self.current_user).copied()
+    }
+
+    /// Tells the server we have seen the newest message of `conversation`, once it has told us
+    /// where we were and as long as the page is in view.
+    fn mark_read(&mut self, conversation: &Conversation) {
+        if self.connection != ConnectionState::Open || page_hidden() {
+            return;
+        }
+        let newest = match self.conversation_messages(conversation).and_then(|m| history::newest_id(m)) {
+            Some(id) => id,
+            None => return,
+        };
+        let position = match self.reads.get_mut(conversation) {
+            Some(positions) => positions.entry(self.current_user.clone()).or_default(),
+            None => return,
+        };
+        if newest <= *position {
+            return;
+        }
+        *position = newest;
+        let (room, to) = match conversation {
+            Conversation::Room(room) => (Some(room.clone()), None),
+            Conversation::Direct(user) => (None, Some(user.clone())),
+        };
+        self.send(ClientMessage::Read { room, to, id: newest });
+    }
+
+    /// Asks the server about the pages linked from `conversation` we have no preview of yet,
+    /// when previews are on.
+    fn request_previews(&mut self, conversation: &Conversation) {
+        if !self.link_previews || self.connection != ConnectionState::Open {
+            return;
+        }
+        let linked: Vec<String> = self
+            .conversation_messages(conversation)
+            .into_iter()
+            .flatten()
+            .filter(|m| !m.deleted)
+            .filter_map(|m| preview_link(&self.message_links(m)).cloned())
+            .collect();
+        for url in linked {
+            let ask = match self.previews.get(&url) {
+                None => true,
+                Some(Preview::Missing(at)) => time::now().saturating_sub(*at) >= PREVIEW_RETRY_MS,
+                Some(_) => false,
+            };
+            if ask {
+                self.previews.insert(url.clone(), Preview::Requested);
+                self.send(ClientMessage::PreviewRequest { url });
+            }
+        }
+    }
+
+    /// The addresses `m` links to, parsed only when its text is new or was edited.
+    fn message_links(&self, m: &MessageData) -> Rc<[String]> {
+        let key = links_key(m);
+        let mut cache = self.links.borrow_mut();
+        match cache.get(&key) {
+            Some((text, links)) if *text == m.message => links.clone(),
+            _ => {
+                let links: Rc<[String]> = markdown::links(&m.message).into();
+                cache.insert(key, (m.message.clone(), links.clone()));
+                links
+            }
+        }
+    }
+
+    /// Tells the others when we went idle or came back, or picked another presence.
+    fn update_presence(&mut self) {
+        let idle = page_hidden() || time::now().saturating_sub(self.last_active) >= AWAY_AFTER_MS;
+        let status = self.presence.effective(idle);
+        if status != self.announced {
+            self.announced = status;
+            self.send(ClientMessage::Presence { status });
+        }
+    }
+
+    fn join(&mut self, room: &str) {
+        if self.joined.insert(room.to_string()) {
+            self.send(ClientMessage::Join {
+                room: room.to_string(),
+            });
+        }
+    }
+
+    /// Online users taking part in `conversation`, followed by those who went offline;
+    /// everyone for direct messages.
+    fn members(&self, conversation: &Conversation) -> Vec<UserProfile> {
+        let room = match conversation {
+            Conversation::Room(room) => room,
+            Conversation::Direct(_) => return self.users.clone(),
+        };
+        match self.rooms.iter().find(|r| &r.id == room) {
+            Some(info) => self
+                .users
+                .iter()
+                .filter(|u| info.members.contains(&u.name) || u.status == Presence::Offline)
+                .cloned()
+                .collect(),
+            None => self.users.clone(),
+        }
+    }
+
+    // `<Link<Route>>` expands to code clippy flags.
+    #[allow(clippy::unnecessary_operation)]
+    fn view_rooms(&self, ctx: &Context<Self>) -> Html {
+        let current = match &ctx.props().conversation {
+            Conversation::Room(room) => Some(room),
+            Conversation::Direct(_) => None,
+        };
+        let mut ids: BTreeSet<&String> = self.rooms.iter().map(|r| &r.id).collect();
+        ids.extend(self.joined.iter());
+
+        let create = ctx.link().callback(|e: FocusEvent| {
+            e.prevent_default();
+            Msg::CreateRoom
+        });
+
+        html! {
+            <>
+                {
+                    ids.into_iter().map(|id| {
+                        let members = self.rooms.iter().find(|r| &r.id == id).map_or(0, |r| r.members.len());
+                        let classes = if Some(id) == current {
+                            "flex justify-between mx-3 my-1 px-3 py-2 rounded-lg bg-blue-600 border border-blue-500"
+                        } else {
+                            "flex justify-between mx-3 my-1 px-3 py-2 rounded-lg bg-gray-700 border border-gray-600 hover:bg-gray-600"
+                        };
+                        html! {
+                            <Link<Route> to={room_route(id)}>
+                                <div class={classes}>
+                                    <span class="text-sm font-medium">{format!("#{}", id)}</span>
+                                    <span class="text-xs text-gray-300">{members}</span>
+                                </div>
+                            </Link<Route>>
+                        }
+                    }).collect::<Html>()
+                }
+                <form onsubmit={create} class="flex mx-3 my-2">
+                    <input
+                        ref={self.room_input.clone()}
+                        type="text"
+                        placeholder="New room"
+                        class="w-full min-w-0 px-3 py-1 text-sm rounded-l-lg bg-gray-700 border border-gray-600 outline-none focus:border-blue-500 text-white placeholder-gray-400"
+                    />
+                    <button type="submit" class="px-3 text-sm rounded-r-lg bg-blue-600 hover:bg-blue-700 border border-blue-500">{"+"}</button>
+                </form>
+            </>
+        }
+    }
+
+    #[allow(clippy::unnecessary_operation)]
+    fn view_directs(&self, ctx: &Context<Self>) -> Html {
+        let mut peers: Vec<&String> = self.directs.keys().collect();
+        peers.sort();
+
+        if peers.is_empty() {
+            return html! {
+                <div class="mx-3 my-2 text-xs text-gray-400">{"Click a user to start a conversation."}</div>
+            };
+        }
+
+        peers.into_iter().map(|peer| {
+            let is_open = ctx.props().conversation == Conversation::Direct(peer.clone());
+            let classes = if is_open {
+                "flex justify-between mx-3 my-1 px-3 py-2 rounded-lg bg-blue-600 border border-blue-500"
+            } else {
+                "flex justify-between mx-3 my-1 px-3 py-2 rounded-lg bg-gray-700 border border-gray-600 hover:bg-gray-600"
+            };
+            let unread = self.unread.get(peer).copied().unwrap_or_default();
+            html! {
+                <Link<Route> to={Route::Direct { user: peer.clone() }}>
+                    <div class={classes}>
+                        <span class="text-sm font-medium">{format!("@{}", peer)}</span>
+                        if unread > 0 {
+                            <span class="text-xs bg-red-600 px-2 rounded-full">{unread}</span>
+                        }
+                    </div>
+                </Link<Route>>
+            }
+        }).collect()
+    }
+
+    fn view_load_older(&self, ctx: &Context<Self>) -> Html {
+        let room = match &ctx.props().conversation {
+            Conversation::Room(room) => room,
+            Conversation::Direct(_) => return html! {},
+        };
+        if !self.has_more.get(room).copied().unwrap_or_default() {
+            return html! {};
+        }
+        let loading = self.loading_history.contains(room);
+        let onclick = ctx.link().callback(|_| Msg::LoadOlder);
+        html! {
+            <div class="flex justify-center mb-2">
+                <button {onclick} disabled={loading} class="px-3 py-1 text-xs rounded-full border border-gray-600 text-gray-300 hover:bg-gray-700">
+                    if loading { {"Loading…"} } else { {"Load older messages"} }
+                </button>
+            </div>
+        }
+    }
+
+    fn view_messages(&self, ctx: &Context<Self>, messages: &[MessageData]) -> Html {
+        let now = time::now();
+        let mut previous: Option<&MessageData> = None;
+        let mut rendered = Vec::with_capacity(messages.len());
+        let threads = history::Threads::new(messages);
+        let first_unread = self
+            .unread_after
+            .and_then(|position| history::first_unread(messages, position, &self.current_user));
+        // Everyone else's read position, shown under the last message they have seen.
+        let mut read_by: HashMap<u64, Vec<&str>> = HashMap::new();
+        if let Some(positions) = self.reads.get(&ctx.props().conversation) {
+            for (name, position) in positions.iter().filter(|(name, _)| **name != self.current_user) {
+                if let Some(id) = history::last_read(messages, *position) {
+                    read_by.entry(id).or_default().push(name);
+                }
+            }
+        }
+
+        for m in messages {
+            let new_day = m.time != 0
+                && previous.is_none_or(|p| p.time == 0 || time::day(p.time) != time::day(m.time));
+            if new_day {
+                rendered.push(html! {
+                    <div class="flex items-center my-4 text-xs text-gray-400">
+                        <div class="grow border-t border-gray-700"></div>
+                        <div class="px-3">{time::date_label(now, m.time)}</div>
+                        <div class="grow border-t border-gray-700"></div>
+                    </div>
+                });
+            }
+            if first_unread == Some(m.id) {
+                rendered.push(html! {
+                    <div class="flex items-center my-4 text-xs text-red-400">
+                        <div class="grow border-t border-red-500"></div>
+                        <div class="px-3">{"New messages"}</div>
+                        <div class="grow border-t border-red-500"></div>
+                    </div>
+                });
+            }
+            let continues_group = !new_day
+                && first_unread != Some(m.id)
+                && previous.is_some_and(|p| continues_group(p, m));
+            rendered.push(self.view_message(ctx, &threads, m, continues_group, now));
+            if let Some(names) = read_by.get(&m.id) {
+                rendered.push(html! {
+                    <div class="flex justify-end gap-1 mt-1" title={format!("Read by {}", names.join(", "))}>
+                        {
+                            names.iter().map(|name| html! {
+                                <img class="w-4 h-4 rounded-full border border-gray-600" src={self.avatar_of(name)} alt={name.to_string()}/>
+                            }).collect::<Html>()
+                        }
+                    </div>
+                });
+            }
+            previous = Some(m);
+        }
+
+        rendered.into_iter().collect()
+    }
+
+    fn avatar_of(&self, name: &str) -> String {
+        // History can contain messages from users the server hasn't told us about.
+        self.users
+            .iter()
+            .find(|u| u.name == name)
+            .map_or_else(|| avatar::url(&Profile::default(), name), |u| u.avatar.clone())
+    }
+
+    fn display_name(&self, name: &str) -> String {
+        self.users
+            .iter()
+            .find(|u| u.name == name)
+            .map_or(name, |u| u.profile.display_name(name))
+            .to_string()
+    }
+
+    /// Everything `name` has told about themselves, over the rest of the page.
+    #[allow(clippy::unnecessary_operation)]
+    fn view_profile_card(&self, ctx: &Context<Self>, name: &str) -> Html {
+        let close = ctx.link().callback(|_| Msg::HideProfile);
+        let user = self.users.iter().find(|u| u.name == name);
+        let profile = user.map(|u| u.profile.clone()).unwrap_or_default();
+        let status = match user {
+            Some(UserProfile {
+                status: Presence::Offline,
+                last_seen: Some(last_seen),
+                ..
+            }) => format!("Last seen {}", last_seen_label(time::now(), *last_seen)),
+            Some(u) => u.status.label().to_string(),
+            None => Presence::Offline.label().to_string(),
+        };
+        let message = {
+            let name = name.to_string();
+            ctx.link().callback(move |_| Msg::OpenDirect(name.clone()))
+        };
+
+        html! {
+            <div onclick={close} class="fixed inset-0 z-10 flex items-center justify-center bg-black bg-opacity-50">
+                <div onclick={Callback::from(|e: MouseEvent| e.stop_propagation())} class="w-72 p-6 flex flex-col items-center rounded-lg bg-gray-800 border border-gray-600">
+                    <img class="w-24 h-24 rounded-full border-2 border-gray-500" src={self.avatar_of(name)} alt="avatar"/>
+                    <div class="mt-3 text-lg font-semibold">{profile.display_name(name).to_string()}</div>
+                    <div class="text-sm text-gray-400">
+                        {format!("@{}", name)}
+                        if !profile.pronouns.is_empty() {
+                            {format!(" · {}", profile.pronouns)}
+                        }
+                    </div>
+                    <div class="mt-2 text-xs text-gray-300">{status}</div>
+                    if !profile.status_text.is_empty() {
+                        <div class="mt-2 text-sm italic text-gray-300 text-center">{profile.status_text.clone()}</div>
+                    }
+                    if name == self.current_user {
+                        <Link<Route> to={Route::Profile} classes="mt-4 px-4 py-2 text-sm rounded-full bg-blue-600 hover:bg-blue-700 border border-blue-500">
+                            {"Edit profile"}
+                        </Link<Route>>
+                    } else {
+                        <button onclick={message} class="mt-4 px-4 py-2 text-sm rounded-full bg-blue-600 hover:bg-blue-700 border border-blue-500">
+                            {"Message"}
+                        </button>
+                    }
+                </div>
+            </div>
+        }
+    }
+
+    /// Renders a single bubble. Bubbles that continue a group leave out the sender's name and
+    /// avatar, keeping the space so the group stays aligned.
+    fn view_message(
+        &self,
+        ctx: &Context<Self>,
+        threads: &history::Threads,
+        m: &MessageData,
+        continues_group: bool,
+        now: u64,
+    ) -> Html {
+        let avatar_src = self.avatar_of(&m.from);
+        let is_current_user = m.from == self.current_user;
+        let delivery = self.delivery(m);
+        let editable = is_current_user && delivery == Delivery::Sent && m.id != 0 && !m.deleted;
+        let replies = if m.id == 0 { 0 } else { threads.reply_count(m.id) };
+
+        let row_classes = match (is_current_user, continues_group) {
+            (true, false) => "flex items-end justify-end w-full mt-4",
+            (true, true) => "flex items-end justify-end w-full mt-1",
+            (false, false) => "flex items-end w-full mt-4",
+            (false, true) => "flex items-end w-full mt-1",
+        };
+
+        let bubble_classes = if is_current_user {
+            "bg-blue-600 text-white rounded-tl-lg rounded-tr-lg rounded-bl-lg border border-blue-500 max-w-xs lg:max-w-md"
+        } else {
+            "bg-gray-700 text-white rounded-tl-lg rounded-tr-lg rounded-br-lg border border-gray-600 max-w-xs lg:max-w-md"
+        };
+
+        let avatar = |classes: &'static str| {
+            if continues_group {
+                html! { <div class={classes}></div> }
+            } else {
+                html! { <img class={classes} src={avatar_src.clone()} alt="avatar"/> }
+            }
+        };
+
+        html! {
+            <div class={classes!("group", row_classes, (delivery == Delivery::Pending).then_some("opacity-60"))}>
+                if !is_current_user {
+                    { avatar("w-8 h-8 rounded-full mr-3 border-2 border-gray-600 flex-none") }
+                }
+                <div class={bubble_classes}>
+                    <div class="p-3">
+                        if !continues_group {
+                            <button class="block text-sm font-medium mb-1 hover:underline" onclick={
+                                let from = m.from.clone();
+                                ctx.link().callback(move |_| Msg::ShowProfile(from.clone()))
+                            }>
+                                if is_current_user {
+                                    {"You"}
+                                } else {
+                                    {self.display_name(&m.from)}
+                                }
+                            </button>
+                        }
+                        if let Some(parent) = m.parent {
+                            <button class="block max-w-full mb-2 pl-2 border-l-2 border-gray-400 text-left text-xs text-gray-300 hover:text-white truncate" onclick={
+                                ctx.link().callback(move |_| Msg::OpenThread(parent))
+                            }>
+                                {self.quote(threads.get(parent))}
+                            </button>
+                        }
+                        if m.deleted {
+                            <div class="text-sm italic text-gray-300">{"This message was deleted."}</div>
+                        } else {
+                            <div class="text-sm">
+                                { self.view_body(m) }
+                            </div>
+                        }
+                        if delivery == Delivery::Pending {
+                            <div class="mt-1 text-xs text-gray-300 text-right">{"Sending…"}</div>
+                        } else if delivery == Delivery::Failed {
+                            <div class="mt-1 text-xs text-red-300 text-right">
+                                {
+                                    match self.refusal(m) {
+                                        Some(reason) => format!("Not sent: {} · ", reason),
+                                        None => "Not sent · ".to_string(),
+                                    }
+                                }
+                                <button class="underline hover:text-white" onclick={
+                                    let nonce = m.nonce.clone().unwrap_or_default();
+                                    ctx.link().callback(move |_| Msg::RetrySend(nonce.clone()))
+                                }>{"Retry"}</button>
+                            </div>
+                        } else if m.time != 0 {
+                            <div class="mt-1 text-xs text-gray-300 text-right" title={time::absolute(m.time)}>
+                                {time::clock(m.time)}
+                                if !time::relative(now, m.time).is_empty() {
+                                    {format!(" · {}", time::relative(now, m.time))}
+                                }
+                                if let Some(edited) = m.edited.filter(|_| !m.deleted) {
+                                    <span title={time::absolute(edited)}>{" · (edited)"}</span>
+                                }
+                            </div>
+                        }
+                        { self.view_reactions(ctx, m) }
+                        if replies > 0 {
+                            <button class="mt-1 text-xs text-gray-300 underline hover:text-white" onclick={
+                                let id = m.id;
+                                ctx.link().callback(move |_| Msg::OpenThread(id))
+                            }>
+                                {format!("💬 {} repl{}", replies, if replies == 1 { "y" } else { "ies" })}
+                            </button>
+                        }
+                        if m.id != 0 && !m.deleted {
+                            <div class="mt-1 text-xs text-right text-gray-300 invisible group-hover:visible">
+                                <button class="underline hover:text-white" onclick={
+                                    let id = m.id;
+                                    ctx.link().callback(move |_| Msg::Reply(id))
+                                }>{"Reply"}</button>
+                                if editable {
+                                    {" · "}
+                                    <button class="underline hover:text-white" onclick={
+                                        let id = m.id;
+                                        ctx.link().callback(move |_| Msg::StartEdit(id))
+                                    }>{"Edit"}</button>
+                                    {" · "}
+                                    <button class="underline hover:text-white" onclick={
+                                        let id = m.id;
+                                        ctx.link().callback(move |_| Msg::DeleteMessage(id))
+                                    }>{"Delete"}</button>
+                                }
+                            </div>
+                        }
+                    </div>
+                </div>
+                if is_current_user {
+                    { avatar("w-8 h-8 rounded-full ml-3 border-2 border-blue-500 flex-none") }
+                }
+            </div>
+        }
+    }
+
+    /// The side panel with every loaded message of the thread started by `root`.
+    fn view_thread(&self, ctx: &Context<Self>, messages: &[MessageData], root: u64) -> Html {
+        let close = ctx.link().callback(|_| Msg::CloseThread);
+        let threads = history::Threads::new(messages);
+        let thread = threads.thread(root);
+        let reply = thread.iter().rev().find(|m| m.id != 0).map(|m| {
+            let id = m.id;
+            ctx.link().callback(move |_| Msg::Reply(id))
+        });
+        let now = time::now();
+
+        html! {
+            <div class="flex-none w-96 h-screen flex flex-col bg-gray-800 border-l border-gray-700">
+                <div class="h-14 flex items-center justify-between border-b border-gray-700">
+                    <div class="text-xl p-3 text-white font-semibold">{"🧵 Thread"}</div>
+                    <button onclick={close} class="mr-4 px-3 py-1 text-sm rounded-full border border-gray-600 hover:bg-gray-700">
+                        {"Close"}
+                    </button>
+                </div>
+                <div class="grow overflow-auto p-4">
+                    if thread.first().is_none_or(|m| m.id != root) {
+                        <div class="mb-2 text-xs text-gray-400">{"The start of this thread isn't loaded."}</div>
+                    }
+                    { thread.iter().map(|m| self.view_message(ctx, &threads, m, false, now)).collect::<Html>() }
+                </div>
+                if let Some(onclick) = reply {
+                    <div class="p-3 border-t border-gray-700">
+                        <button {onclick} class="w-full py-2 text-sm rounded-full bg-blue-600 hover:bg-blue-700 border border-blue-500">
+                            {"Reply in thread"}
+                        </button>
+                    </div>
+                }
+            </div>
+        }
+    }
+
+    /// Counts of every reaction, toggling ours on click, plus a picker for new ones on hover.
+    fn view_reactions(&self, ctx: &Context<Self>, m: &MessageData) -> Html {
+        if m.id == 0 || m.deleted {
+            return html! {};
+        }
+        let react = |emoji: &str| {
+            let (id, emoji) = (m.id, emoji.to_string());
+            ctx.link().callback(move |_| Msg::React(id, emoji.clone()))
+        };
+        let unused: Vec<&str> = REACTION_EMOJIS
+            .iter()
+            .copied()
+            .filter(|emoji| !m.reactions.iter().any(|r| r.emoji == *emoji))
+            .collect();
+
+        html! {
+            <div class="flex flex-wrap items-center gap-1 mt-2">
+                {
+                    m.reactions.iter().filter(|r| !r.users.is_empty()).map(|r| {
+                        let ours = r.users.contains(&self.current_user);
+                        let classes = if ours {
+                            "px-2 py-0.5 text-xs rounded-full bg-blue-800 border border-blue-400"
+                        } else {
+                            "px-2 py-0.5 text-xs rounded-full bg-gray-800 border border-gray-500 hover:border-gray-300"
+                        };
+                        html! {
+                            <button class={classes} title={r.users.join(", ")} onclick={react(&r.emoji)}>
+                                {format!("{} {}", r.emoji, r.users.len())}
+                            </button>
+                        }
+                    }).collect::<Html>()
+                }
+                <div class="invisible group-hover:visible flex gap-1">
+                    {
+                        unused.into_iter().map(|emoji| html! {
+                            <button class="px-1 text-xs rounded-full hover:bg-gray-600" title="React" onclick={react(emoji)}>
+                                {emoji}
+                            </button>
+                        }).collect::<Html>()
+                    }
+                </div>
+            </div>
+        }
+    }
+
+    fn drop_frame(&mut self, error: DecodeError, frame: String) {
+        if let DecodeError::UnknownType(_) = error {
+            log::debug!("Ignoring frame: {}", error);
+            self.ignored_frames += 1;
+            return;
+        }
+        log::warn!("Dropping frame: {}", error);
+        self.dropped_frames += 1;
+        if self.recent_drops.len() == RECENT_DROPS {
+            self.recent_drops.remove(0);
+        }
+        self.recent_drops.push(DroppedFrame {
+            error,
+            frame: frame.chars().take(DROPPED_FRAME_PREVIEW).collect(),
+        });
+    }
+
+    fn view_typing(&self, conversation: &Conversation) -> Html {
+        let names: Vec<&str> = self
+            .typing
+            .iter()
+            .filter(|(c, _)| c == conversation)
+            .map(|(_, name)| name.as_str())
+            .collect();
+        let text = match names.as_slice() {
+            [] => String::new(),
+            [name] => format!("{} is typing…", name),
+            [first, second] => format!("{} and {} are typing…", first, second),
+            _ => "Several people are typing…".to_string(),
+        };
+        html! {
+            <div class="w-full h-6 px-7 text-xs italic text-gray-400 bg-gray-900">{text}</div>
+        }
+    }
+
+    /// A message's text, followed by the images and videos it links to and the preview card
+    /// of the first other page. A message that is just an image link only shows the image.
+    fn view_body(&self, m: &MessageData) -> Html {
+        let text = &m.message;
+        let links = self.message_links(m);
+        let embedded: Vec<(String, Media)> = links
+            .iter()
+            .filter_map(|url| media::inline(url).map(|kind| (url.clone(), kind)))
+            .take(MAX_INLINE_MEDIA)
+            .collect();
+        let only_media = matches!(embedded.as_slice(), [(url, _)] if text.trim() == url);
+        let preview = if self.link_previews {
+            preview_link(&links)
+                .and_then(|url| match self.previews.get(url) {
+                    Some(Preview::Loaded(preview)) => Some((preview, url.clone())),
+                    _ => None,
+                })
+        } else {
+            None
+        };
+        html! {
+            <>
+                if !only_media {
+                    { markdown::render(text) }
+                }
+                {
+                    embedded.into_iter().map(|(url, kind)| match kind {
+                        Media::Image => html! {
+                            <img class="mt-2 rounded max-w-full max-h-80" src={url} alt="" loading="lazy" referrerpolicy="no-referrer"/>
+                        },
+                        Media::Video => html! {
+                            <video class="mt-2 rounded max-w-full max-h-80" src={url} controls=true preload="metadata"></video>
+                        },
+                    }).collect::<Html>()
+                }
+                if let Some((preview, url)) = preview {
+                    { view_link_preview(preview, url) }
+                }
+            </>
+        }
+    }
+
+    fn view_presence_picker(&self, ctx: &Context<Self>) -> Html {
+        Presence::CHOICES
+            .iter()
+            .map(|presence| {
+                let presence = *presence;
+                let classes = if presence == self.presence {
+                    "mr-1 px-1 rounded bg-blue-800"
+                } else {
+                    "mr-1 px-1 rounded hover:bg-blue-700"
+                };
+                html! {
+                    <button class={classes} onclick={ctx.link().callback(move |_| Msg::SetPresence(presence))}>
+                        {presence.label()}
+                    </button>
+                }
+            })
+            .collect()
+    }
+
+    fn view_connection_banner(&self) -> Html {
+        let server = &self.config.server_url;
+        let (text, classes) = match self.connection {
+            ConnectionState::Open => return html! {},
+            ConnectionState::Connecting => (
+                format!("Connecting to {}…", server),
+                "bg-gray-700 border-gray-600",
+            ),
+            ConnectionState::Reconnecting => (
+                format!("Connection to {} lost. Reconnecting…", server),
+                "bg-yellow-700 border-yellow-600",
+            ),
+            ConnectionState::Closed => (
+                format!("Disconnected from {}.", server),
+                "bg-red-700 border-red-600",
+            ),
+        };
+        html! {
+            <div class={format!("w-full px-4 py-2 text-sm text-white border-b {}", classes)}>
+                {text}
+            </div>
+        }
+    }
+}
+
+/// Whether `next` can be shown under `previous` without repeating the sender.
+fn continues_group(previous: &MessageData, next: &MessageData) -> bool {
+    previous.from == next.from && next.time.saturating_sub(previous.time) < GROUP_WINDOW_MS
+}
+
+/// Tells our own messages apart until the server has given them ids. Only ever compared with
+/// nonces from the same sender.
+fn new_nonce() -> String {
+    let random = (js_sys::Math::random() * f64::from(u32::MAX)) as u32;
+    format!("{:x}-{:08x}", time::now(), random)
+}
+
+/// Whether the browser tab is in the background. Without a document, nobody is looking.
+fn page_hidden() -> bool {
+    web_sys::window()
+        .and_then(|w| w.document())
+        .is_none_or(|d| d.hidden())
+}
+
+/// What the links of `m` are cached by: its id, or its nonce until it has one.
+fn links_key(m: &MessageData) -> String {
+    match &m.nonce {
+        Some(nonce) if m.id == 0 => nonce.clone(),
+        _ => m.id.to_string(),
+    }
+}
+
+/// The page a message linking to `links` gets a preview card for: the first web address that
+/// isn't shown as an image or a video already.
+fn preview_link(links: &[String]) -> Option<&String> {
+    links
+        .iter()
+        .find(|url| media::host(url).is_some() && media::inline(url).is_none())
+}
+
+fn view_link_preview(preview: &LinkPreview, url: String) -> Html {
+    // The page picks its picture, so it has to come from a host we'd show images from anyway.
+    let image = preview
+        .image
+        .clone()
+        .filter(|image| media::inline(image) == Some(Media::Image));
+    html! {
+        <a href={url} target="_blank" rel="noopener noreferrer" class="flex mt-2 max-w-sm rounded overflow-hidden border-l-4 border-blue-400 bg-gray-900 bg-opacity-40 hover:bg-opacity-70">
+            <div class="min-w-0 p-2">
+                <div class="text-xs text-gray-400 truncate">{preview.site_name.clone()}</div>
+                if !preview.title.is_empty() {
+                    <div class="text-sm font-semibold truncate">{preview.title.clone()}</div>
+                }
+                if !preview.description.is_empty() {
+                    <div class="max-h-8 overflow-hidden text-xs text-gray-300">{preview.description.clone()}</div>
+                }
+            </div>
+            if let Some(image) = image {
+                <img class="flex-none w-20 h-20 object-cover" src={image} alt="" loading="lazy" referrerpolicy="no-referrer"/>
+            }
+        </a>
+    }
+}
+
+fn last_seen_label(now: u64, then: u64) -> String {
+    match time::relative(now, then) {
+        relative if relative.is_empty() => format!("on {}", time::date_label(now, then)),
+        relative => relative,
+    }
+}
+
+fn presence_color(presence: Presence) -> &'static str {
+    match presence {
+        Presence::Online => "bg-green-500",
+        Presence::Away => "bg-yellow-500",
+        Presence::DoNotDisturb => "bg-red-500",
+        Presence::Offline => "bg-gray-500",
+    }
+}
+
+fn typing_frame(conversation: &Conversation, active: bool) -> ClientMessage {
+    let (room, to) = match conversation {
+        Conversation::Room(room) => (Some(room.clone()), None),
+        Conversation::Direct(user) => (None, Some(user.clone())),
+    };
+    if active {
+        ClientMessage::TypingStart { room, to }
+    } else {
+        ClientMessage::TypingStop { room, to }
+    }
+}
+
+fn room_route(id: &str) -> Route {
+    if id == DEFAULT_ROOM {
+        Route::Chat
+    } else {
+        Route::Room { id: id.to_string() }
+    }
+}
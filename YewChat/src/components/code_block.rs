@@ -0,0 +1,88 @@
+use gloo_timers::callback::Timeout;
+use js_sys::{Function, Promise, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use yew::functional::*;
+use yew::prelude::*;
+
+use crate::highlight::{self, Token};
+
+/// How long the copy button says it copied.
+const COPIED_MS: u32 = 2 * 1000;
+
+#[derive(Properties, PartialEq)]
+pub struct CodeBlockProps {
+    pub lang: Option<String>,
+    pub code: String,
+}
+
+/// A fenced code block: highlighted, scrolling sideways instead of wrapping, with a button
+/// copying the code.
+#[function_component(CodeBlock)]
+pub fn code_block(props: &CodeBlockProps) -> Html {
+    let copied = use_state(|| false);
+
+    let copy = {
+        let code = props.code.clone();
+        let copied = copied.clone();
+        Callback::from(move |_| {
+            let code = code.clone();
+            let copied = copied.clone();
+            spawn_local(async move {
+                match write_clipboard(&code).await {
+                    Ok(()) => {
+                        copied.set(true);
+                        Timeout::new(COPIED_MS, move || copied.set(false)).forget();
+                    }
+                    Err(e) => log::warn!("Could not copy to the clipboard: {:?}", e),
+                }
+            });
+        })
+    };
+
+    html! {
+        <div class="my-1 rounded border border-gray-600 bg-gray-900 text-xs">
+            <div class="flex justify-between items-center px-2 py-1 border-b border-gray-700 text-gray-400">
+                <span>{props.lang.clone().unwrap_or_default()}</span>
+                <button onclick={copy} class="px-2 rounded hover:bg-gray-700 hover:text-white">
+                    if *copied { {"Copied!"} } else { {"Copy"} }
+                </button>
+            </div>
+            <pre class="p-2 overflow-x-auto whitespace-pre font-mono"><code>
+                {
+                    highlight::highlight(props.lang.as_deref(), &props.code)
+                        .into_iter()
+                        .map(|(token, text)| match token_class(token) {
+                            Some(class) => html! { <span {class}>{text}</span> },
+                            None => html! { {text} },
+                        })
+                        .collect::<Html>()
+                }
+            </code></pre>
+        </div>
+    }
+}
+
+fn token_class(token: Token) -> Option<&'static str> {
+    match token {
+        Token::Plain => None,
+        Token::Keyword => Some("text-purple-300"),
+        Token::Type => Some("text-yellow-200"),
+        Token::String => Some("text-green-300"),
+        Token::Number => Some("text-orange-300"),
+        Token::Comment => Some("text-gray-500 italic"),
+        Token::Macro => Some("text-blue-300"),
+        Token::Variable => Some("text-pink-300"),
+    }
+}
+
+/// `navigator.clipboard.writeText`, looked up at runtime: web-sys only has bindings for the
+/// clipboard API behind `web_sys_unstable_apis`.
+async fn write_clipboard(text: &str) -> Result<(), JsValue> {
+    let window = web_sys::window().ok_or_else(|| JsValue::from_str("no window"))?;
+    let navigator = Reflect::get(&window, &JsValue::from_str("navigator"))?;
+    let clipboard = Reflect::get(&navigator, &JsValue::from_str("clipboard"))?;
+    let write_text: Function = Reflect::get(&clipboard, &JsValue::from_str("writeText"))?.dyn_into()?;
+    let promise: Promise = write_text.call1(&clipboard, &JsValue::from_str(text))?.dyn_into()?;
+    JsFuture::from(promise).await.map(|_| ())
+}
@@ -0,0 +1,68 @@
+use yew::prelude::*;
+
+use crate::protocol::DecodeError;
+
+/// A frame from the server that `Chat` could not decode.
+#[derive(Clone, PartialEq)]
+pub struct DroppedFrame {
+    pub error: DecodeError,
+    pub frame: String,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct DiagnosticsProps {
+    /// Total number of frames dropped since the chat was opened.
+    pub dropped: usize,
+    /// Frames of a type this client doesn't know, left out of the drops as they aren't broken.
+    pub ignored: usize,
+    /// The most recent drops, oldest first.
+    pub recent: Vec<DroppedFrame>,
+}
+
+#[function_component(Diagnostics)]
+pub fn diagnostics(props: &DiagnosticsProps) -> Html {
+    let expanded = use_state(|| false);
+
+    if props.dropped == 0 && props.ignored == 0 {
+        return html! {};
+    }
+
+    let toggle = {
+        let expanded = expanded.clone();
+        Callback::from(move |_| expanded.set(!*expanded))
+    };
+
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    if props.dropped == 0 {
+        return html! {
+            <div class="m-3 p-2 text-xs text-gray-400">
+                {format!("{} frame{} of unknown types ignored", props.ignored, plural(props.ignored))}
+            </div>
+        };
+    }
+
+    html! {
+        <div class="m-3 rounded-lg border border-yellow-600 bg-gray-700 text-xs">
+            <button onclick={toggle} class="w-full p-2 text-left text-yellow-300 font-medium">
+                {format!("⚠️ {} dropped frame{}", props.dropped, plural(props.dropped))}
+                if props.ignored > 0 {
+                    <span class="ml-2 font-normal text-gray-400">
+                        {format!("({} of unknown types ignored)", props.ignored)}
+                    </span>
+                }
+            </button>
+            if *expanded {
+                <ul class="max-h-64 overflow-auto border-t border-gray-600">
+                    {
+                        props.recent.iter().rev().map(|d| html! {
+                            <li class="p-2 border-b border-gray-600">
+                                <div class="text-gray-200">{d.error.to_string()}</div>
+                                <div class="mt-1 font-mono text-gray-400 break-all">{d.frame.clone()}</div>
+                            </li>
+                        }).collect::<Html>()
+                    }
+                </ul>
+            }
+        </div>
+    }
+}
@@ -1,38 +1,132 @@
 use web_sys::HtmlInputElement;
 use yew::functional::*;
 use yew::prelude::*;
+use yew_agent::use_bridge;
 use yew_router::prelude::*;
 
+use crate::protocol::{self, ServerMessage};
+use crate::services::event_bus::{EventBus, Request};
+use crate::services::storage;
+use crate::session::{self, Event, Step};
 use crate::Route;
-use crate::User;
+use crate::{Socket, User};
 
 #[function_component(Login)]
 pub fn login() -> Html {
-    let username = use_state(|| String::new());
+    let username = use_state(String::new);
+    let password = use_state(String::new);
+    // The login sent to the server and not answered yet.
+    let pending = use_state(|| None::<session::Login>);
+    let error = use_state(|| None::<String>);
     let user = use_context::<User>().expect("No context found.");
+    let socket = use_context::<Socket>().expect("No socket context found.");
+    let history = use_history();
+
+    {
+        let pending = pending.clone();
+        let error = error.clone();
+        let user = user.clone();
+        let socket = socket.clone();
+        use_bridge::<EventBus, _>(move |req| {
+            let login = match &*pending {
+                Some(login) => login,
+                None => return,
+            };
+            let event = match req {
+                Request::EventBusMsg(frame) => match ServerMessage::decode(&frame) {
+                    Ok(message) => Event::Frame(Box::new(message)),
+                    Err(_) => return,
+                },
+                Request::ConnectionState(state) => Event::Connection(state),
+            };
+            match login.advance(event) {
+                Step::Ignored => {}
+                Step::Waiting { login, send } => {
+                    for message in send {
+                        if let Err(e) = socket.tx.clone().try_send(message.encode()) {
+                            log::debug!("Error sending to channel: {:?}", e);
+                        }
+                    }
+                    pending.set(Some(login));
+                }
+                Step::Done { name, token } => {
+                    pending.set(None);
+                    storage::save_username(&name);
+                    storage::save_token(&token);
+                    *user.token.borrow_mut() = token;
+                    *user.username.borrow_mut() = name;
+                    if let Some(history) = &history {
+                        history.push(Route::Chat);
+                    }
+                }
+                Step::Failed(reason) => {
+                    pending.set(None);
+                    error.set(Some(reason));
+                }
+            }
+        });
+    }
+
+    if !user.username.borrow().is_empty() && !user.token.borrow().is_empty() {
+        return html! {<Redirect<Route> to={Route::Chat} />};
+    }
 
     let oninput = {
         let current_username = username.clone();
+        let error = error.clone();
         Callback::from(move |e: InputEvent| {
             let input: HtmlInputElement = e.target_unchecked_into();
             current_username.set(input.value());
+            error.set(None);
+        })
+    };
+
+    let onpassword = {
+        let password = password.clone();
+        let error = error.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            password.set(input.value());
+            error.set(None);
         })
     };
 
-    let onclick = {
+    let onsubmit = {
         let username = username.clone();
-        let user = user.clone();
-        Callback::from(move |_| *user.username.borrow_mut() = (*username).clone())
+        let password = password.clone();
+        let pending = pending.clone();
+        let error = error.clone();
+        Callback::from(move |e: FocusEvent| {
+            e.prevent_default();
+            if let Err(reason) = protocol::validate_username(&username) {
+                error.set(Some(reason.to_string()));
+                return;
+            }
+            let (login, auth) = session::Login::start((*username).clone(), (*password).clone());
+            if let Err(e) = socket.tx.clone().try_send(auth.encode()) {
+                log::debug!("Error sending to channel: {:?}", e);
+                error.set(Some("Could not reach the server, try again.".to_string()));
+                return;
+            }
+            pending.set(Some(login));
+        })
     };
 
     html! {
         <div class="bg-gray-800 flex w-screen">
             <div class="container mx-auto flex flex-col justify-center items-center">
-                <form class="m-4 flex">
+                <form {onsubmit} class="m-4 flex">
                     <input {oninput} class="rounded-l-lg p-4 border-t mr-0 border-b border-l text-gray-800 border-gray-200 bg-white" placeholder="Username" />
-                    <Link<Route> to={Route::Chat}><button {onclick} disabled={username.len() < 1} class="px-8 rounded-r-lg bg-violet-600 text-white font-bold p-4 uppercase border-violet-600 border-t border-b border-r">{"Go Chatting"}</button></Link<Route>>
+                    <input oninput={onpassword} type="password" class="p-4 border-t mr-0 border-b border-l text-gray-800 border-gray-200 bg-white" placeholder="Password" />
+                    <button type="submit" disabled={username.is_empty() || password.is_empty() || pending.is_some()} class="px-8 rounded-r-lg bg-violet-600 text-white font-bold p-4 uppercase border-violet-600 border-t border-b border-r">{"Go Chatting"}</button>
                 </form>
+                if pending.as_ref().is_some_and(session::Login::interrupted) {
+                    <div class="text-gray-400 text-sm">{"Connection lost, logging in again once it's back…"}</div>
+                }
+                if let Some(error) = &*error {
+                    <div class="text-red-400 text-sm">{error.clone()}</div>
+                }
             </div>
         </div>
     }
-}
\ No newline at end of file
+}
@@ -1,2 +1,5 @@
 pub mod chat;
-pub mod login;
\ No newline at end of file
+pub mod code_block;
+pub mod diagnostics;
+pub mod login;
+pub mod profile;
\ No newline at end of file
@@ -0,0 +1,137 @@
+use web_sys::HtmlInputElement;
+use yew::functional::*;
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use crate::avatar;
+use crate::protocol::{
+    ClientMessage, Profile, AVATAR_STYLES, MAX_AVATAR_SEED_LEN, MAX_DISPLAY_NAME_LEN,
+    MAX_PRONOUNS_LEN, MAX_STATUS_TEXT_LEN,
+};
+use crate::services::storage;
+use crate::Route;
+use crate::{Socket, User};
+
+#[function_component(ProfileEditor)]
+pub fn profile_editor() -> Html {
+    let user = use_context::<User>().expect("No context found.");
+    let socket = use_context::<Socket>().expect("No socket context found.");
+    let history = use_history();
+    let profile = use_state(|| storage::load_profile().unwrap_or_default());
+    let error = use_state(|| None::<String>);
+    let username = user.username.borrow().clone();
+
+    let edit = |field: fn(&mut Profile, String)| {
+        let profile = profile.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let mut edited = (*profile).clone();
+            field(&mut edited, input.value());
+            profile.set(edited);
+        })
+    };
+    let pick_style = |style: &'static str| {
+        let profile = profile.clone();
+        Callback::from(move |_| {
+            profile.set(Profile {
+                avatar_style: style.to_string(),
+                ..(*profile).clone()
+            })
+        })
+    };
+
+    let onsubmit = {
+        let profile = profile.clone();
+        let error = error.clone();
+        Callback::from(move |e: FocusEvent| {
+            e.prevent_default();
+            let profile = profile.normalized();
+            let update = ClientMessage::UpdateProfile {
+                profile: profile.clone(),
+            };
+            if let Err(e) = socket.tx.clone().try_send(update.encode()) {
+                log::debug!("Error sending to channel: {:?}", e);
+                error.set(Some("Could not reach the server, try again.".to_string()));
+                return;
+            }
+            storage::save_profile(&profile);
+            if let Some(history) = &history {
+                history.push(Route::Chat);
+            }
+        })
+    };
+
+    let field = |label: &'static str, value: &str, max: usize, oninput: Callback<InputEvent>| {
+        html! {
+            <label class="block mt-4 text-sm text-gray-300">
+                {label}
+                <input {oninput} value={value.to_string()} maxlength={max.to_string()} class="block w-full mt-1 px-3 py-2 rounded-lg bg-gray-700 border border-gray-600 outline-none focus:border-blue-500 text-white" />
+            </label>
+        }
+    };
+
+    html! {
+        <div class="bg-gray-800 flex w-screen text-white">
+            <div class="container mx-auto flex flex-col justify-center items-center">
+                <form {onsubmit} class="w-96 m-4 p-6 rounded-lg bg-gray-900 border border-gray-700">
+                    <div class="flex items-center">
+                        <img class="w-20 h-20 rounded-full border-2 border-gray-500" src={avatar::url(&profile, &username)} alt="avatar"/>
+                        <div class="ml-4">
+                            <div class="text-lg font-semibold">{profile.display_name(&username).to_string()}</div>
+                            <div class="text-sm text-gray-400">{format!("@{}", username)}</div>
+                        </div>
+                    </div>
+                    { field("Display name", &profile.display_name, MAX_DISPLAY_NAME_LEN, edit(|p, v| p.display_name = v)) }
+                    { field("Pronouns", &profile.pronouns, MAX_PRONOUNS_LEN, edit(|p, v| p.pronouns = v)) }
+                    { field("Status", &profile.status_text, MAX_STATUS_TEXT_LEN, edit(|p, v| p.status_text = v)) }
+                    { field("Avatar seed", &profile.avatar_seed, MAX_AVATAR_SEED_LEN, edit(|p, v| p.avatar_seed = v)) }
+                    if avatar::PICKS_STYLE {
+                        <div class="mt-4 text-sm text-gray-300">{"Avatar style"}</div>
+                        <div class="flex flex-wrap gap-2 mt-1">
+                            {
+                                AVATAR_STYLES.iter().enumerate().map(|(i, style)| {
+                                    let chosen = profile.avatar_style == *style
+                                        || (i == 0 && !AVATAR_STYLES.contains(&profile.avatar_style.as_str()));
+                                    let classes = if chosen {
+                                        "rounded-lg p-1 border-2 border-blue-500"
+                                    } else {
+                                        "rounded-lg p-1 border-2 border-gray-700 hover:border-gray-500"
+                                    };
+                                    let preview = Profile {
+                                        avatar_style: style.to_string(),
+                                        ..(*profile).clone()
+                                    };
+                                    html! {
+                                        <button type="button" class={classes} title={*style} onclick={pick_style(style)}>
+                                            <img class="w-10 h-10" src={avatar::url(&preview, &username)} alt={*style}/>
+                                        </button>
+                                    }
+                                }).collect::<Html>()
+                            }
+                        </div>
+                    }
+                    if let Some(error) = &*error {
+                        <div class="mt-4 text-red-400 text-sm">{error.clone()}</div>
+                    }
+                    <div class="flex justify-end mt-6">
+                        { cancel_link() }
+                        <button type="submit" class="ml-2 px-4 py-2 text-sm rounded-full bg-blue-600 hover:bg-blue-700 border border-blue-500">
+                            {"Save"}
+                        </button>
+                    </div>
+                </form>
+            </div>
+        </div>
+    }
+}
+
+// `<Link<Route>>` expands to code clippy flags, and `#[function_component]` doesn't pass the
+// allow on to it.
+#[allow(clippy::unnecessary_operation)]
+fn cancel_link() -> Html {
+    html! {
+        <Link<Route> to={Route::Chat} classes="px-4 py-2 text-sm rounded-full border border-gray-600 hover:bg-gray-700">
+            {"Cancel"}
+        </Link<Route>>
+    }
+}
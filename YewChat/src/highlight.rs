@@ -0,0 +1,275 @@
+//! Syntax highlighting for fenced code blocks.
+//!
+//! A small tokenizer per language rather than a full grammar: it only has to tell keywords,
+//! strings, comments and the like apart well enough to colour a snippet pasted into the chat.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Plain,
+    Keyword,
+    Type,
+    String,
+    Number,
+    Comment,
+    Macro,
+    Variable,
+}
+
+struct Syntax {
+    keywords: &'static [&'static str],
+    line_comment: &'static str,
+    block_comments: bool,
+    quotes: &'static [char],
+    /// Single-quoted strings without escapes, as in the shell.
+    raw_single_quotes: bool,
+    /// `'a` is a lifetime unless it looks like a char literal.
+    lifetimes: bool,
+    raw_strings: bool,
+    /// `name!` is a macro call.
+    macros: bool,
+    /// Capitalised identifiers are types.
+    types: bool,
+    /// `$name`, `${name}` and `$1` are variables.
+    variables: bool,
+    /// Words may contain dashes, e.g. `--all-targets`.
+    dashed_words: bool,
+}
+
+const RUST: Syntax = Syntax {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while",
+    ],
+    line_comment: "//",
+    block_comments: true,
+    quotes: &['"', '\''],
+    raw_single_quotes: false,
+    lifetimes: true,
+    raw_strings: true,
+    macros: true,
+    types: true,
+    variables: false,
+    dashed_words: false,
+};
+
+const SHELL: Syntax = Syntax {
+    keywords: &[
+        "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case",
+        "esac", "in", "function", "return", "export", "local", "set", "unset",
+    ],
+    line_comment: "#",
+    block_comments: false,
+    quotes: &['"', '\''],
+    raw_single_quotes: true,
+    lifetimes: false,
+    raw_strings: false,
+    macros: false,
+    types: false,
+    variables: true,
+    dashed_words: true,
+};
+
+fn syntax(lang: &str) -> Option<&'static Syntax> {
+    match lang.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => Some(&RUST),
+        "sh" | "bash" | "shell" | "zsh" | "console" => Some(&SHELL),
+        _ => None,
+    }
+}
+
+/// `code` split into runs of the same kind; all plain for languages we don't know.
+pub fn highlight(lang: Option<&str>, code: &str) -> Vec<(Token, String)> {
+    let syntax = match lang.and_then(syntax) {
+        Some(syntax) => syntax,
+        None => return vec![(Token::Plain, code.to_string())],
+    };
+    let chars: Vec<char> = code.chars().collect();
+    let mut tokens: Vec<(Token, String)> = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let (token, end) = next_token(syntax, &chars, i);
+        let text: String = chars[i..end].iter().collect();
+        match tokens.last_mut() {
+            Some((last, run)) if *last == token => run.push_str(&text),
+            _ => tokens.push((token, text)),
+        }
+        i = end;
+    }
+    tokens
+}
+
+/// The kind of the token starting at `start`, and where it ends.
+fn next_token(syntax: &Syntax, chars: &[char], start: usize) -> (Token, usize) {
+    let at = |i: usize, s: &str| s.chars().enumerate().all(|(k, c)| chars.get(i + k) == Some(&c));
+    let c = chars[start];
+
+    // A `#` inside a word, as in `$#` or `a#b`, doesn't start a shell comment.
+    let comment_allowed = syntax.line_comment != "#" || start == 0 || chars[start - 1].is_whitespace();
+    if at(start, syntax.line_comment) && comment_allowed {
+        let end = find(chars, start, "\n").unwrap_or(chars.len());
+        return (Token::Comment, end);
+    }
+    if syntax.block_comments && at(start, "/*") {
+        let end = find(chars, start + 2, "*/").map_or(chars.len(), |i| i + 2);
+        return (Token::Comment, end);
+    }
+    if syntax.raw_strings && c == 'r' {
+        let hashes = chars[start + 1..].iter().take_while(|c| **c == '#').count();
+        if chars.get(start + 1 + hashes) == Some(&'"') {
+            let closing = format!("\"{}", "#".repeat(hashes));
+            let end = find(chars, start + 2 + hashes, &closing).map_or(chars.len(), |i| i + closing.len());
+            return (Token::String, end);
+        }
+    }
+    if syntax.quotes.contains(&c) {
+        let char_literal = chars.get(start + 1) == Some(&'\\') || chars.get(start + 2) == Some(&'\'');
+        if c != '\'' || !syntax.lifetimes || char_literal {
+            let escapes = c != '\'' || !syntax.raw_single_quotes;
+            return (Token::String, string_end(chars, start, escapes));
+        }
+    }
+    if syntax.variables && c == '$' {
+        let end = match chars.get(start + 1) {
+            Some('{') => find(chars, start + 2, "}").map_or(chars.len(), |i| i + 1),
+            Some(n) if n.is_alphabetic() || *n == '_' => word_end(chars, start + 1, false),
+            Some(n) if n.is_ascii_digit() || "@#?*!$-".contains(*n) => start + 2,
+            _ => return (Token::Plain, start + 1),
+        };
+        return (Token::Variable, end);
+    }
+    if c.is_ascii_digit() {
+        let mut end = start + 1;
+        while let Some(n) = chars.get(end) {
+            let decimal_point = *n == '.' && chars.get(end + 1).is_some_and(char::is_ascii_digit);
+            if !(n.is_alphanumeric() || *n == '_' || decimal_point) {
+                break;
+            }
+            end += 1;
+        }
+        return (Token::Number, end);
+    }
+    if c.is_alphabetic() || c == '_' {
+        let end = word_end(chars, start, syntax.dashed_words);
+        let word: String = chars[start..end].iter().collect();
+        if syntax.keywords.contains(&word.as_str()) {
+            return (Token::Keyword, end);
+        }
+        if syntax.macros && chars.get(end) == Some(&'!') && chars.get(end + 1) != Some(&'=') {
+            return (Token::Macro, end + 1);
+        }
+        if syntax.types && c.is_uppercase() {
+            return (Token::Type, end);
+        }
+        return (Token::Plain, end);
+    }
+    (Token::Plain, start + 1)
+}
+
+fn word_end(chars: &[char], start: usize, dashes: bool) -> usize {
+    start
+        + chars[start..]
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || **c == '_' || (dashes && **c == '-'))
+            .count()
+}
+
+/// Index of the next `pattern` at or after `from`.
+fn find(chars: &[char], from: usize, pattern: &str) -> Option<usize> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    (from..chars.len()).find(|i| chars[*i..].starts_with(&pattern))
+}
+
+/// End of the string opening at `start`. Unterminated single-quoted strings stop at the end of
+/// the line, others run to the end of the code.
+fn string_end(chars: &[char], start: usize, escapes: bool) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while let Some(c) = chars.get(i) {
+        match c {
+            '\\' if escapes => i += 2,
+            '\n' if quote == '\'' => return i,
+            c if *c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Everything but the plain runs of `code`.
+    fn kinds(lang: &str, code: &str) -> Vec<(Token, String)> {
+        highlight(Some(lang), code)
+            .into_iter()
+            .filter(|(token, _)| *token != Token::Plain)
+            .collect()
+    }
+
+    fn tokens(expected: &[(Token, &str)]) -> Vec<(Token, String)> {
+        expected.iter().map(|(token, text)| (*token, text.to_string())).collect()
+    }
+
+    #[test]
+    fn rust() {
+        assert_eq!(
+            kinds("rust", "fn main<'a>() { let s: String = r#\"x\"#; println!(\"{}\\\"\", 'c', 1_000u64); } // done"),
+            tokens(&[
+                (Token::Keyword, "fn"),
+                (Token::Keyword, "let"),
+                (Token::Type, "String"),
+                (Token::String, "r#\"x\"#"),
+                (Token::Macro, "println!"),
+                (Token::String, "\"{}\\\"\""),
+                (Token::String, "'c'"),
+                (Token::Number, "1_000u64"),
+                (Token::Comment, "// done"),
+            ])
+        );
+        assert_eq!(
+            kinds("rs", "a /* b */ != c"),
+            tokens(&[(Token::Comment, "/* b */")])
+        );
+    }
+
+    #[test]
+    fn shell() {
+        assert_eq!(
+            kinds("bash", "for f in $FILES; do echo \"$f\" 'a\\b' $# # note\ndone"),
+            tokens(&[
+                (Token::Keyword, "for"),
+                (Token::Keyword, "in"),
+                (Token::Variable, "$FILES"),
+                (Token::Keyword, "do"),
+                (Token::String, "\"$f\""),
+                (Token::String, "'a\\b'"),
+                (Token::Variable, "$#"),
+                (Token::Comment, "# note"),
+                (Token::Keyword, "done"),
+            ])
+        );
+        assert_eq!(kinds("sh", "cargo do-it"), vec![]);
+    }
+
+    #[test]
+    fn unknown_languages_stay_plain() {
+        assert_eq!(
+            highlight(Some("cobol"), "MOVE 1 TO X"),
+            vec![(Token::Plain, "MOVE 1 TO X".to_string())]
+        );
+        assert_eq!(
+            highlight(None, "fn x"),
+            vec![(Token::Plain, "fn x".to_string())]
+        );
+    }
+
+    #[test]
+    fn text_is_preserved() {
+        let code = "let x = \"unterminated\n  fn y() {}\t";
+        let joined: String = highlight(Some("rust"), code).into_iter().map(|(_, t)| t).collect();
+        assert_eq!(joined, code);
+    }
+}
@@ -0,0 +1,313 @@
+//! Keeping message buffers ordered and free of duplicates when pages of history, live
+//! messages, re-sent history after a reconnect and our own not yet echoed messages all land in
+//! the same buffer.
+
+use std::collections::HashMap;
+
+use crate::protocol::MessageData;
+
+/// Adds `incoming` to `buffer`, skipping messages whose id is already present, and keeps the
+/// buffer sorted by id. Returns how many messages were added or confirmed.
+///
+/// The echo of a pending message replaces it, matched by sender and nonce; a second copy of it
+/// is skipped like any other duplicate. Messages without an id stay after the others in the
+/// order they arrived: pending ones, and those from servers that don't assign ids.
+pub fn merge(buffer: &mut Vec<MessageData>, incoming: Vec<MessageData>) -> usize {
+    let mut changed = 0;
+    for message in incoming {
+        let same_nonce = message.nonce.is_some().then(|| {
+            buffer
+                .iter()
+                .position(|m| m.nonce == message.nonce && m.from == message.from)
+        });
+        match same_nonce.flatten() {
+            Some(i) if buffer[i].is_pending() && message.id != 0 => buffer[i] = message,
+            Some(_) => continue,
+            None if message.id != 0 && buffer.iter().any(|m| m.id == message.id) => continue,
+            None => buffer.push(message),
+        }
+        changed += 1;
+    }
+    if changed > 0 {
+        buffer.sort_by_key(|m| (m.id == 0, m.id));
+    }
+    changed
+}
+
+/// Replaces the message with the same id as `message`, e.g. after it was edited or deleted.
+/// Returns whether it was found.
+pub fn update(buffer: &mut [MessageData], message: MessageData) -> bool {
+    match buffer.iter_mut().find(|m| m.id != 0 && m.id == message.id) {
+        Some(existing) => {
+            *existing = message;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Who replied to what in a buffer, worked out once so that reply counts, quotes and threads
+/// don't each go through the whole buffer for every message shown.
+pub struct Threads<'a> {
+    buffer: &'a [MessageData],
+    /// Where each message with an id is in the buffer.
+    positions: HashMap<u64, usize>,
+    /// Where the replies to each message are in the buffer, oldest first.
+    replies: HashMap<u64, Vec<usize>>,
+}
+
+impl<'a> Threads<'a> {
+    pub fn new(buffer: &'a [MessageData]) -> Self {
+        let mut positions = HashMap::new();
+        let mut replies: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, m) in buffer.iter().enumerate() {
+            if m.id != 0 {
+                positions.insert(m.id, i);
+            }
+            if let Some(parent) = m.parent.filter(|parent| *parent != 0 && *parent != m.id) {
+                replies.entry(parent).or_default().push(i);
+            }
+        }
+        Self {
+            buffer,
+            positions,
+            replies,
+        }
+    }
+
+    /// The loaded message with the id `id`.
+    pub fn get(&self, id: u64) -> Option<&'a MessageData> {
+        self.positions.get(&id).map(|i| &self.buffer[*i])
+    }
+
+    /// How many loaded messages reply to `id` directly.
+    pub fn reply_count(&self, id: u64) -> usize {
+        self.replies.get(&id).map_or(0, Vec::len)
+    }
+
+    /// Id of the message that started the thread `id` belongs to, following replies up as far
+    /// as they are loaded. A message that isn't a reply is its own root.
+    pub fn root(&self, id: u64) -> u64 {
+        let mut root = id;
+        // Ids only ever point to older messages, but don't trust that blindly.
+        for _ in 0..self.buffer.len() {
+            match self.get(root).and_then(|m| m.parent) {
+                Some(parent) if parent != root => root = parent,
+                _ => break,
+            }
+        }
+        root
+    }
+
+    /// The thread started by `root`: the root message if loaded, then all replies, oldest first.
+    pub fn thread(&self, root: u64) -> Vec<&'a MessageData> {
+        let mut seen = vec![false; self.buffer.len()];
+        let mut found: Vec<usize> = self.positions.get(&root).copied().into_iter().collect();
+        for &i in &found {
+            seen[i] = true;
+        }
+        let mut parents = vec![root];
+        while let Some(parent) = parents.pop() {
+            for &i in self.replies.get(&parent).into_iter().flatten() {
+                if !seen[i] {
+                    seen[i] = true;
+                    found.push(i);
+                    parents.push(self.buffer[i].id);
+                }
+            }
+        }
+        found.sort_unstable();
+        found.into_iter().map(|i| &self.buffer[i]).collect()
+    }
+}
+
+/// Id of the newest message someone who read up to `position` has seen.
+pub fn last_read(buffer: &[MessageData], position: u64) -> Option<u64> {
+    buffer
+        .iter()
+        .map(|m| m.id)
+        .filter(|id| *id != 0 && *id <= position)
+        .max()
+}
+
+/// Id of the first message from somebody else than `me` after the read position `position`.
+pub fn first_unread(buffer: &[MessageData], position: u64, me: &str) -> Option<u64> {
+    buffer
+        .iter()
+        .filter(|m| m.id > position && m.from != me)
+        .map(|m| m.id)
+        .min()
+}
+
+/// Id of the newest message the server has confirmed.
+pub fn newest_id(buffer: &[MessageData]) -> Option<u64> {
+    buffer.iter().map(|m| m.id).max().filter(|id| *id != 0)
+}
+
+/// Id of the oldest message in `buffer`, used as the cursor for loading older history.
+pub fn oldest_id(buffer: &[MessageData]) -> Option<u64> {
+    buffer.iter().map(|m| m.id).filter(|id| *id != 0).min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::DEFAULT_ROOM;
+
+    fn message(id: u64) -> MessageData {
+        MessageData {
+            id,
+            from: "alice".into(),
+            message: format!("message {}", id),
+            time: id * 1000,
+            room: DEFAULT_ROOM.into(),
+            to: None,
+            nonce: None,
+            edited: None,
+            deleted: false,
+            reactions: vec![],
+            parent: None,
+        }
+    }
+
+    fn sent(id: u64, nonce: &str) -> MessageData {
+        MessageData {
+            nonce: Some(nonce.into()),
+            ..message(id)
+        }
+    }
+
+    fn ids(buffer: &[MessageData]) -> Vec<u64> {
+        buffer.iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn older_pages_are_inserted_in_order() {
+        let mut buffer = vec![message(5), message(6)];
+        assert_eq!(merge(&mut buffer, vec![message(3), message(4)]), 2);
+        assert_eq!(ids(&buffer), vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn duplicates_are_skipped() {
+        let mut buffer = vec![message(1), message(2)];
+        assert_eq!(merge(&mut buffer, vec![message(2), message(3)]), 1);
+        assert_eq!(ids(&buffer), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn messages_without_ids_are_appended() {
+        let mut buffer = vec![message(0)];
+        assert_eq!(merge(&mut buffer, vec![message(0)]), 1);
+        assert_eq!(buffer.len(), 2);
+    }
+
+    #[test]
+    fn echo_replaces_the_pending_message() {
+        let mut buffer = vec![message(1), sent(0, "a"), sent(0, "b")];
+        assert_eq!(merge(&mut buffer, vec![message(2), sent(3, "b")]), 2);
+        assert_eq!(ids(&buffer), vec![1, 2, 3, 0]);
+        assert!(buffer[3].is_pending());
+        assert_eq!(buffer[3].nonce.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn repeated_echoes_are_skipped() {
+        let mut buffer = vec![sent(0, "a")];
+        assert_eq!(merge(&mut buffer, vec![sent(4, "a")]), 1);
+        assert_eq!(merge(&mut buffer, vec![sent(4, "a")]), 0);
+        assert_eq!(ids(&buffer), vec![4]);
+    }
+
+    #[test]
+    fn nonces_only_match_the_same_sender() {
+        let mut buffer = vec![sent(0, "a")];
+        let other = MessageData {
+            from: "bob".into(),
+            ..sent(5, "a")
+        };
+        assert_eq!(merge(&mut buffer, vec![other]), 1);
+        assert_eq!(ids(&buffer), vec![5, 0]);
+    }
+
+    #[test]
+    fn updates_replace_by_id() {
+        let mut buffer = vec![message(1), message(2)];
+        let edited = MessageData {
+            message: "fixed".into(),
+            edited: Some(5000),
+            ..message(2)
+        };
+        assert!(update(&mut buffer, edited.clone()));
+        assert_eq!(buffer[1], edited);
+        assert!(!update(&mut buffer, message(3)));
+        assert_eq!(ids(&buffer), vec![1, 2]);
+    }
+
+    fn reply(id: u64, parent: u64) -> MessageData {
+        MessageData {
+            parent: Some(parent),
+            ..message(id)
+        }
+    }
+
+    fn thread_ids(buffer: &[MessageData], root: u64) -> Vec<u64> {
+        Threads::new(buffer).thread(root).iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn threads_follow_replies_to_replies() {
+        let buffer = vec![message(1), reply(2, 1), message(3), reply(4, 2), reply(5, 3)];
+        let threads = Threads::new(&buffer);
+        assert_eq!(threads.root(4), 1);
+        assert_eq!(threads.root(1), 1);
+        assert_eq!(thread_ids(&buffer, 1), vec![1, 2, 4]);
+        assert_eq!(threads.reply_count(1), 1);
+        assert_eq!(threads.reply_count(4), 0);
+        assert_eq!(threads.get(3), Some(&buffer[2]));
+        assert_eq!(threads.get(0), None);
+    }
+
+    #[test]
+    fn threads_stop_at_unloaded_parents() {
+        let buffer = vec![reply(7, 3), reply(8, 7), reply(0, 8)];
+        assert_eq!(Threads::new(&buffer).root(8), 3);
+        assert_eq!(thread_ids(&buffer, 3), vec![7, 8, 0]);
+    }
+
+    #[test]
+    fn threads_survive_replies_to_themselves() {
+        let buffer = vec![reply(1, 2), reply(2, 1), reply(3, 3)];
+        let threads = Threads::new(&buffer);
+        assert_eq!(threads.root(3), 3);
+        assert_eq!(threads.reply_count(3), 0);
+        assert_eq!(thread_ids(&buffer, 1), vec![1, 2]);
+    }
+
+    #[test]
+    fn long_threads_are_indexed_once() {
+        let buffer: Vec<_> = (1..20_000).map(|id| reply(id, id.saturating_sub(1).max(1))).collect();
+        let threads = Threads::new(&buffer);
+        assert_eq!(threads.thread(1).len(), buffer.len());
+        let replies: usize = buffer.iter().map(|m| threads.reply_count(m.id)).sum();
+        assert_eq!(replies, buffer.len() - 1);
+    }
+
+    #[test]
+    fn read_positions_map_to_loaded_messages() {
+        let mut buffer = vec![message(2), message(5), message(0)];
+        buffer[1].from = "bob".into();
+        assert_eq!(last_read(&buffer, 4), Some(2));
+        assert_eq!(last_read(&buffer, 1), None);
+        assert_eq!(first_unread(&buffer, 2, "alice"), Some(5));
+        assert_eq!(first_unread(&buffer, 2, "bob"), None);
+        assert_eq!(newest_id(&buffer), Some(5));
+        assert_eq!(newest_id(&[message(0)]), None);
+    }
+
+    #[test]
+    fn oldest_id_ignores_missing_ids() {
+        assert_eq!(oldest_id(&[message(0), message(7), message(4)]), Some(4));
+        assert_eq!(oldest_id(&[message(0)]), None);
+    }
+}
@@ -1,10 +1,23 @@
 #![recursion_limit = "512"]
 
+mod avatar;
 mod components;
+mod highlight;
+mod history;
+mod markdown;
+mod media;
+mod protocol;
 mod services;
+mod session;
+mod time;
 
 use components::login::Login;
-use components::chat::Chat;
+use components::chat::{Chat, Conversation};
+use components::profile::ProfileEditor;
+use protocol::{room_id, ClientMessage, DEFAULT_ROOM};
+use services::config::AppConfig;
+use services::storage;
+use services::websocket::WebsocketService;
 
 
 use wasm_bindgen::prelude::*;
@@ -23,29 +36,78 @@ use std::rc::Rc;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-#[derive(Debug, Clone, Copy, PartialEq, Routable)]
+#[derive(Debug, Clone, PartialEq, Routable)]
 pub enum Route {
     #[at("/")]
     Login,
     #[at("/chat")]
     Chat,
+    #[at("/room/:id")]
+    Room { id: String },
+    #[at("/direct/:user")]
+    Direct { user: String },
+    #[at("/profile")]
+    Profile,
     #[not_found]
     #[at("/404")]
     NotFound,
 }
 
 pub type User = Rc<UserInner>;
+/// Settings read from the page at startup.
+pub type Config = Rc<AppConfig>;
+/// The one connection to the server, shared by the login page and the chat.
+pub type Socket = Rc<WebsocketService>;
 
 #[derive(Debug, PartialEq)]
 pub struct UserInner {
+    /// Empty until the user has logged in.
     pub username: RefCell<String>,
+    /// Session token signed by the server; empty until the user has logged in.
+    pub token: RefCell<String>,
 }
 
+#[derive(Properties, PartialEq)]
+struct RequireUserProps {
+    children: Children,
+}
+
+/// Renders its children only for a logged in user, redirecting to the login page otherwise.
+#[function_component(RequireUser)]
+fn require_user(props: &RequireUserProps) -> Html {
+    let user = use_context::<User>().expect("No context found.");
+    if user.username.borrow().is_empty() || user.token.borrow().is_empty() {
+        html! {<Redirect<Route> to={Route::Login} />}
+    } else {
+        html! {<>{ for props.children.iter() }</>}
+    }
+}
+
+// `html!` expands to code clippy flags when rendering components.
+#[allow(clippy::let_unit_value, clippy::unnecessary_operation)]
 fn switch(selected_route: &Route) -> Html {
-    match selected_route {
-        Route::Login => html! {<Login />},
-        Route::Chat => html! {<Chat />},
-        Route::NotFound => html! {<h1>{"404 baby"}</h1>},
+    let conversation = match selected_route {
+        Route::Login => return html! {<Login />},
+        Route::NotFound => return html! {<h1>{"404 baby"}</h1>},
+        Route::Profile => {
+            return html! {
+                <RequireUser>
+                    <ProfileEditor />
+                </RequireUser>
+            }
+        }
+        Route::Chat => Conversation::Room(DEFAULT_ROOM.to_string()),
+        // The server ignores rooms with other ids, e.g. `/room/Rust` is `rust`.
+        Route::Room { id } => match room_id(id) {
+            Some(room) => Conversation::Room(room),
+            None => return switch(&Route::NotFound),
+        },
+        Route::Direct { user } => Conversation::Direct(user.clone()),
+    };
+    html! {
+        <RequireUser>
+            <Chat {conversation} />
+        </RequireUser>
     }
 }
 
@@ -53,18 +115,58 @@ fn switch(selected_route: &Route) -> Html {
 fn main() -> Html {
     let ctx = use_state(|| {
         Rc::new(UserInner {
-            username: RefCell::new("initial".into()),
+            username: RefCell::new(storage::load_username().unwrap_or_default()),
+            token: RefCell::new(storage::load_token().unwrap_or_default()),
         })
     });
+    let config = use_state(|| Rc::new(AppConfig::from_page()));
+    let socket = {
+        let user = (*ctx).clone();
+        let config = (*config).clone();
+        // Whoever is logged in authenticates with their token and registers again every time
+        // the connection is (re)established.
+        use_state(move || {
+            Rc::new(WebsocketService::new(&config.server_url, move || {
+                let username = user.username.borrow();
+                let token = user.token.borrow();
+                if username.is_empty() || token.is_empty() {
+                    return vec![];
+                }
+                vec![
+                    ClientMessage::Auth {
+                        data: username.clone(),
+                        password: None,
+                        token: Some(token.clone()),
+                    }
+                    .encode(),
+                    ClientMessage::Register {
+                        data: username.clone(),
+                    }
+                    .encode(),
+                ]
+            }))
+        })
+    };
+
+    app((*config).clone(), (*ctx).clone(), (*socket).clone())
+}
 
+// `html!` expands to code clippy flags for generic components, and `#[function_component]`
+// doesn't pass the allow on to it.
+#[allow(clippy::unnecessary_operation)]
+fn app(config: Config, user: User, socket: Socket) -> Html {
     html! {
-        <ContextProvider<User> context={(*ctx).clone()}>
+        <ContextProvider<Config> context={config}>
+        <ContextProvider<User> context={user}>
+        <ContextProvider<Socket> context={socket}>
         <BrowserRouter>
             <div class="flex w-screen h-screen">
                 <Switch<Route> render={Switch::render(switch)}/>
             </div>
         </BrowserRouter>
+        </ContextProvider<Socket>>
         </ContextProvider<User>>
+        </ContextProvider<Config>>
     }
 }
 
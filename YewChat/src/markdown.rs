@@ -0,0 +1,613 @@
+//! The Markdown subset messages are written in: **bold**, *italics*, `inline code`, fenced
+//! code blocks, [links](https://example.com), lists and block quotes. Web addresses written out
+//! as is become links too.
+//!
+//! Messages are parsed into [`Block`]s and rendered as Yew nodes, so whatever markup a message
+//! contains only ever ends up as text. Links are only kept for web and mail addresses.
+
+use yew::prelude::*;
+
+use crate::components::code_block::CodeBlock;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Paragraph(Vec<Inline>),
+    /// A fenced code block with the language named after the opening fence, if any.
+    Code { lang: Option<String>, code: String },
+    Quote(Vec<Block>),
+    /// A bulleted list, or a numbered one counting from `start`.
+    List {
+        start: Option<u64>,
+        items: Vec<Vec<Inline>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Strong(Vec<Inline>),
+    Emphasis(Vec<Inline>),
+    Code(String),
+    Link { text: Vec<Inline>, url: String },
+    LineBreak,
+}
+
+/// Schemes a link may point to; anything else stays plain text.
+const LINK_SCHEMES: &[&str] = &["http://", "https://", "mailto:"];
+/// Schemes of addresses turned into links without any markup.
+const BARE_LINK_SCHEMES: &[&str] = &["http://", "https://"];
+/// How deep quotes nest; the `>` markers of deeper ones are shown as text.
+const MAX_QUOTE_DEPTH: usize = 8;
+/// How deep emphasis and links nest; deeper delimiters are shown as text.
+const MAX_INLINE_DEPTH: usize = 16;
+
+pub fn parse(text: &str) -> Vec<Block> {
+    let lines: Vec<&str> = text.lines().collect();
+    parse_blocks(&lines, 0)
+}
+
+fn parse_blocks(lines: &[&str], depth: usize) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let trimmed = lines[i].trim_start();
+        if trimmed.is_empty() {
+            i += 1;
+        } else if let Some(info) = trimmed.strip_prefix("```") {
+            let lang = info.trim();
+            let mut code = vec![];
+            i += 1;
+            while i < lines.len() && !lines[i].trim_start().starts_with("```") {
+                code.push(lines[i]);
+                i += 1;
+            }
+            // Skips the closing fence; an unclosed block runs to the end of the message.
+            i += 1;
+            blocks.push(Block::Code {
+                lang: (!lang.is_empty()).then(|| lang.to_string()),
+                code: code.join("\n"),
+            });
+        } else if depth < MAX_QUOTE_DEPTH && quote_line(lines[i]).is_some() {
+            let mut quoted = vec![];
+            while let Some(line) = lines.get(i).and_then(|line| quote_line(line)) {
+                quoted.push(line);
+                i += 1;
+            }
+            blocks.push(Block::Quote(parse_blocks(&quoted, depth + 1)));
+        } else if let Some((start, _)) = list_item(lines[i]) {
+            let mut items: Vec<String> = vec![];
+            while let Some(line) = lines.get(i) {
+                match list_item(line) {
+                    Some((s, text)) if s.is_some() == start.is_some() => items.push(text.to_string()),
+                    Some(_) => break,
+                    // Indented lines continue the item above.
+                    None if line.starts_with([' ', '\t']) && !line.trim().is_empty() => {
+                        if let Some(item) = items.last_mut() {
+                            item.push('\n');
+                            item.push_str(line.trim());
+                        }
+                    }
+                    None => break,
+                }
+                i += 1;
+            }
+            blocks.push(Block::List {
+                start,
+                items: items.iter().map(|item| parse_inline(item)).collect(),
+            });
+        } else {
+            let mut paragraph = vec![trimmed.trim_end()];
+            i += 1;
+            while let Some(line) = lines.get(i).filter(|line| !starts_block(line)) {
+                paragraph.push(line.trim());
+                i += 1;
+            }
+            blocks.push(Block::Paragraph(parse_inline(&paragraph.join("\n"))));
+        }
+    }
+    blocks
+}
+
+/// Whether `line` ends the paragraph above it.
+fn starts_block(line: &str) -> bool {
+    line.trim().is_empty()
+        || line.trim_start().starts_with("```")
+        || quote_line(line).is_some()
+        || list_item(line).is_some()
+}
+
+fn quote_line(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('>')?;
+    Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+/// The number an item starts with (`None` for bullets) and its text.
+fn list_item(line: &str) -> Option<(Option<u64>, &str)> {
+    let trimmed = line.trim_start();
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(text) = trimmed.strip_prefix(bullet) {
+            return Some((None, text));
+        }
+    }
+    let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+    if digits == 0 || digits > 9 {
+        return None;
+    }
+    let rest = &trimmed[digits..];
+    let text = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") "))?;
+    Some((trimmed[..digits].parse().ok(), text))
+}
+
+pub fn parse_inline(text: &str) -> Vec<Inline> {
+    let chars: Vec<char> = text.chars().collect();
+    inline(&chars, 0)
+}
+
+/// Where the spans opening in a run of text could end, worked out once for all of it, so
+/// that a message full of unmatched delimiters isn't scanned again for each one of them.
+struct Scan {
+    depth: usize,
+    /// For each position, the first `]` or line break from there on, or the length.
+    bracket_end: Vec<usize>,
+    /// For each position, the first `)` from there on, or the length.
+    paren_end: Vec<usize>,
+    /// For `*`, `**`, `_` and `__`, the earliest position no closing delimiter follows.
+    unclosed: [usize; 4],
+}
+
+impl Scan {
+    fn new(chars: &[char], depth: usize) -> Self {
+        Self {
+            depth,
+            bracket_end: first_from(chars, |c| c == ']' || c == '\n'),
+            paren_end: first_from(chars, |c| c == ')'),
+            unclosed: [usize::MAX; 4],
+        }
+    }
+}
+
+/// The first position at or after each one where `matches` holds, or the length.
+fn first_from(chars: &[char], matches: impl Fn(char) -> bool) -> Vec<usize> {
+    let mut first = vec![chars.len(); chars.len() + 1];
+    for i in (0..chars.len()).rev() {
+        first[i] = if matches(chars[i]) { i } else { first[i + 1] };
+    }
+    first
+}
+
+fn inline(chars: &[char], depth: usize) -> Vec<Inline> {
+    let mut scan = Scan::new(chars, depth);
+    let mut nodes = vec![];
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let parsed = match chars[i] {
+            '\\' if chars.get(i + 1).is_some_and(char::is_ascii_punctuation) => {
+                text.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '\n' => Some((Inline::LineBreak, i + 1)),
+            '`' => code_span(chars, i),
+            '*' | '_' => emphasis(chars, i, &mut scan),
+            '[' => link(chars, i, &scan),
+            'h' | 'H' if i == 0 || !chars[i - 1].is_alphanumeric() => bare_link(chars, i),
+            _ => None,
+        };
+        match parsed {
+            Some((node, next)) => {
+                if !text.is_empty() {
+                    nodes.push(Inline::Text(std::mem::take(&mut text)));
+                }
+                nodes.push(node);
+                i = next;
+            }
+            None => {
+                text.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    if !text.is_empty() {
+        nodes.push(Inline::Text(text));
+    }
+    nodes
+}
+
+/// The code span opening at `start`, and where the text after it begins.
+fn code_span(chars: &[char], start: usize) -> Option<(Inline, usize)> {
+    let len = chars[start + 1..].iter().position(|c| *c == '`')?;
+    if len == 0 {
+        return None;
+    }
+    let code = chars[start + 1..start + 1 + len].iter().collect();
+    Some((Inline::Code(code), start + len + 2))
+}
+
+/// `*a*`, `_a_`, `**a**` or `__a__` opening at `start`. Underscores only count at word
+/// boundaries, so snake_case names stay as they are.
+fn emphasis(chars: &[char], start: usize, scan: &mut Scan) -> Option<(Inline, usize)> {
+    let delimiter = chars[start];
+    let width = if chars.get(start + 1) == Some(&delimiter) { 2 } else { 1 };
+    let open = start + width;
+    let kind = usize::from(delimiter == '_') * 2 + width - 1;
+    let word_char = |i: usize| chars.get(i).is_some_and(|c| c.is_alphanumeric());
+    if scan.depth >= MAX_INLINE_DEPTH
+        || open >= scan.unclosed[kind]
+        || chars.get(open).is_none_or(|c| c.is_whitespace())
+        || (delimiter == '_' && start > 0 && word_char(start - 1))
+    {
+        return None;
+    }
+    let mut i = open + 1;
+    while i < chars.len() {
+        if chars[i] == '`' {
+            if let Some((_, next)) = code_span(chars, i) {
+                i = next;
+                continue;
+            }
+        }
+        if chars[i] != delimiter {
+            i += 1;
+            continue;
+        }
+        let run = chars[i..].iter().take_while(|c| **c == delimiter).count();
+        let closes = run >= width
+            && (width == 2 || run == 1)
+            && !chars[i - 1].is_whitespace()
+            && !(delimiter == '_' && word_char(i + width));
+        if closes {
+            let inner = inline(&chars[open..i], scan.depth + 1);
+            let node = if width == 2 {
+                Inline::Strong(inner)
+            } else {
+                Inline::Emphasis(inner)
+            };
+            return Some((node, i + width));
+        }
+        i += run;
+    }
+    // Whether a delimiter closes doesn't depend on where it opened, so none opening later
+    // on can close either.
+    scan.unclosed[kind] = open;
+    None
+}
+
+/// `[text](url)` opening at `start`, as long as the URL is a safe one.
+fn link(chars: &[char], start: usize, scan: &Scan) -> Option<(Inline, usize)> {
+    let close = scan.bracket_end[start + 1];
+    if scan.depth >= MAX_INLINE_DEPTH
+        || chars.get(close) != Some(&']')
+        || chars.get(close + 1) != Some(&'(')
+    {
+        return None;
+    }
+    let end = scan.paren_end[close + 2];
+    if end == chars.len() {
+        return None;
+    }
+    let url: String = chars[close + 2..end].iter().collect();
+    if !is_safe_url(&url) {
+        return None;
+    }
+    let text = if close == start + 1 {
+        vec![Inline::Text(url.clone())]
+    } else {
+        unlink(inline(&chars[start + 1..close], scan.depth + 1))
+    };
+    Some((Inline::Link { text, url }, end + 1))
+}
+
+/// A web address starting at `start` without any markup around it. Punctuation ending a
+/// sentence isn't part of it, and neither is a closing parenthesis without an opening one.
+fn bare_link(chars: &[char], start: usize) -> Option<(Inline, usize)> {
+    let prefix: String = chars[start..].iter().take(8).collect();
+    let prefix = prefix.to_ascii_lowercase();
+    if !BARE_LINK_SCHEMES.iter().any(|scheme| prefix.starts_with(scheme)) {
+        return None;
+    }
+    let len = chars[start..]
+        .iter()
+        .take_while(|c| !c.is_whitespace() && !c.is_control() && !matches!(c, '<' | '>' | '"' | '`'))
+        .count();
+    let mut end = start + len;
+    let count = |c: char| chars[start..end].iter().filter(|x| **x == c).count();
+    let (opened, mut closed) = (count('('), count(')'));
+    loop {
+        match chars[end - 1] {
+            '.' | ',' | ':' | ';' | '!' | '?' | '\'' | '*' | '_' => end -= 1,
+            ')' if closed > opened => {
+                end -= 1;
+                closed -= 1;
+            }
+            _ => break,
+        }
+    }
+    let url: String = chars[start..end].iter().collect();
+    if !is_safe_url(&url) {
+        return None;
+    }
+    let text = vec![Inline::Text(url.clone())];
+    Some((Inline::Link { text, url }, end))
+}
+
+/// `nodes` with the links in them turned back into text, as links can't contain links.
+fn unlink(nodes: Vec<Inline>) -> Vec<Inline> {
+    let mut unlinked: Vec<Inline> = vec![];
+    let flattened = nodes.into_iter().flat_map(|node| match node {
+        Inline::Link { text, .. } => text,
+        Inline::Strong(inner) => vec![Inline::Strong(unlink(inner))],
+        Inline::Emphasis(inner) => vec![Inline::Emphasis(unlink(inner))],
+        other => vec![other],
+    });
+    for node in flattened {
+        match (unlinked.last_mut(), node) {
+            (Some(Inline::Text(text)), Inline::Text(more)) => text.push_str(&more),
+            (_, node) => unlinked.push(node),
+        }
+    }
+    unlinked
+}
+
+pub fn is_safe_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    LINK_SCHEMES
+        .iter()
+        .any(|scheme| lower.starts_with(scheme) && lower.len() > scheme.len())
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Addresses of the links in `text`, in order and without repeats.
+pub fn links(text: &str) -> Vec<String> {
+    fn from_blocks(blocks: &[Block], links: &mut Vec<String>) {
+        for block in blocks {
+            match block {
+                Block::Paragraph(inlines) => from_inlines(inlines, links),
+                Block::Quote(blocks) => from_blocks(blocks, links),
+                Block::List { items, .. } => items.iter().for_each(|item| from_inlines(item, links)),
+                Block::Code { .. } => {}
+            }
+        }
+    }
+    fn from_inlines(inlines: &[Inline], links: &mut Vec<String>) {
+        for inline in inlines {
+            match inline {
+                Inline::Link { url, .. } if !links.contains(url) => links.push(url.clone()),
+                Inline::Strong(inner) | Inline::Emphasis(inner) => from_inlines(inner, links),
+                _ => {}
+            }
+        }
+    }
+    let mut links = vec![];
+    from_blocks(&parse(text), &mut links);
+    links
+}
+
+/// `text` as Yew nodes; nothing in it is ever interpreted as HTML.
+pub fn render(text: &str) -> Html {
+    html! {
+        <div class="space-y-1 break-words">
+            { parse(text).iter().map(render_block).collect::<Html>() }
+        </div>
+    }
+}
+
+fn render_block(block: &Block) -> Html {
+    match block {
+        Block::Paragraph(inlines) => html! { <p>{ render_inlines(inlines) }</p> },
+        Block::Code { lang, code } => html! { <CodeBlock lang={lang.clone()} code={code.clone()} /> },
+        Block::Quote(blocks) => html! {
+            <blockquote class="pl-2 border-l-2 border-gray-400 text-gray-300">
+                { blocks.iter().map(render_block).collect::<Html>() }
+            </blockquote>
+        },
+        Block::List { start: None, items } => html! {
+            <ul class="pl-5 list-disc">
+                { items.iter().map(|item| html! { <li>{ render_inlines(item) }</li> }).collect::<Html>() }
+            </ul>
+        },
+        Block::List {
+            start: Some(start),
+            items,
+        } => html! {
+            <ol class="pl-5 list-decimal" start={start.to_string()}>
+                { items.iter().map(|item| html! { <li>{ render_inlines(item) }</li> }).collect::<Html>() }
+            </ol>
+        },
+    }
+}
+
+fn render_inlines(inlines: &[Inline]) -> Html {
+    inlines.iter().map(render_inline).collect()
+}
+
+fn render_inline(inline: &Inline) -> Html {
+    match inline {
+        Inline::Text(text) => html! { {text.clone()} },
+        Inline::Strong(inner) => html! { <strong>{ render_inlines(inner) }</strong> },
+        Inline::Emphasis(inner) => html! { <em>{ render_inlines(inner) }</em> },
+        Inline::Code(code) => html! {
+            <code class="px-1 rounded bg-gray-900 font-mono text-xs">{code.clone()}</code>
+        },
+        Inline::Link { text, url } => html! {
+            <a href={url.clone()} target="_blank" rel="noopener noreferrer" class="underline hover:text-blue-200">
+                { render_inlines(text) }
+            </a>
+        },
+        Inline::LineBreak => html! { <br/> },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(s.to_string())
+    }
+
+    #[test]
+    fn inline_styles() {
+        assert_eq!(
+            parse_inline("a **b** *c* `d*` e"),
+            vec![
+                text("a "),
+                Inline::Strong(vec![text("b")]),
+                text(" "),
+                Inline::Emphasis(vec![text("c")]),
+                text(" "),
+                Inline::Code("d*".into()),
+                text(" e"),
+            ]
+        );
+        assert_eq!(
+            parse_inline("*a **b** c*"),
+            vec![Inline::Emphasis(vec![
+                text("a "),
+                Inline::Strong(vec![text("b")]),
+                text(" c"),
+            ])]
+        );
+    }
+
+    #[test]
+    fn unmatched_and_escaped_delimiters_stay_text() {
+        assert_eq!(parse_inline("2 * 3 * 4"), vec![text("2 * 3 * 4")]);
+        assert_eq!(parse_inline("snake_case_name"), vec![text("snake_case_name")]);
+        assert_eq!(parse_inline(r"\*not\* `"), vec![text("*not* `")]);
+        assert_eq!(
+            parse_inline("_a_ b"),
+            vec![Inline::Emphasis(vec![text("a")]), text(" b")]
+        );
+    }
+
+    #[test]
+    fn links_need_a_safe_scheme() {
+        assert_eq!(
+            parse_inline("see [the docs](https://yew.rs)!"),
+            vec![
+                text("see "),
+                Inline::Link {
+                    text: vec![text("the docs")],
+                    url: "https://yew.rs".into(),
+                },
+                text("!"),
+            ]
+        );
+        assert_eq!(
+            parse_inline("[x](javascript:alert(1))"),
+            vec![text("[x](javascript:alert(1))")]
+        );
+        assert_eq!(
+            parse_inline("[see https://a.org](https://b.org)"),
+            vec![Inline::Link {
+                text: vec![text("see https://a.org")],
+                url: "https://b.org".into(),
+            }]
+        );
+        assert!(is_safe_url("mailto:bob@example.com"));
+        assert!(!is_safe_url("https://"));
+        assert!(!is_safe_url("https://a b"));
+    }
+
+    #[test]
+    fn bare_addresses_become_links() {
+        let link = |url: &str| Inline::Link {
+            text: vec![text(url)],
+            url: url.into(),
+        };
+        assert_eq!(
+            parse_inline("see https://yew.rs/docs, (or https://en.wikipedia.org/wiki/Rust_(language))."),
+            vec![
+                text("see "),
+                link("https://yew.rs/docs"),
+                text(", (or "),
+                link("https://en.wikipedia.org/wiki/Rust_(language)"),
+                text(")."),
+            ]
+        );
+        assert_eq!(
+            parse_inline("**http://a.org/x_y** `https://b.org`"),
+            vec![
+                Inline::Strong(vec![link("http://a.org/x_y")]),
+                text(" "),
+                Inline::Code("https://b.org".into()),
+            ]
+        );
+        assert_eq!(
+            parse_inline("wahttps://a.org javascript:alert(1) https://"),
+            vec![text("wahttps://a.org javascript:alert(1) https://")]
+        );
+        assert_eq!(
+            links("> https://a.org\n- [b](https://b.org) and https://a.org\n```\nhttps://c.org\n```"),
+            vec!["https://a.org".to_string(), "https://b.org".to_string()]
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        let parsed = parse("Hi\nthere\n\n```rust\nlet x = 1;\n\n```\n> quoted\n> *more*\n- one\n  two\n- three\n3. four");
+        assert_eq!(
+            parsed,
+            vec![
+                Block::Paragraph(vec![text("Hi"), Inline::LineBreak, text("there")]),
+                Block::Code {
+                    lang: Some("rust".into()),
+                    code: "let x = 1;\n".into(),
+                },
+                Block::Quote(vec![Block::Paragraph(vec![
+                    text("quoted"),
+                    Inline::LineBreak,
+                    Inline::Emphasis(vec![text("more")]),
+                ])]),
+                Block::List {
+                    start: None,
+                    items: vec![
+                        vec![text("one"), Inline::LineBreak, text("two")],
+                        vec![text("three")],
+                    ],
+                },
+                Block::List {
+                    start: Some(3),
+                    items: vec![vec![text("four")]],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_fences_run_to_the_end() {
+        assert_eq!(
+            parse("```\n<b>raw</b>"),
+            vec![Block::Code {
+                lang: None,
+                code: "<b>raw</b>".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn deep_quotes_flatten_into_text() {
+        let mut blocks = parse(&format!("{}deep", ">".repeat(8000)));
+        let mut depth = 0;
+        while let [Block::Quote(inner)] = blocks.as_slice() {
+            blocks = inner.clone();
+            depth += 1;
+        }
+        assert_eq!(depth, MAX_QUOTE_DEPTH);
+        assert_eq!(
+            blocks,
+            vec![Block::Paragraph(vec![text(&format!("{}deep", ">".repeat(8000 - MAX_QUOTE_DEPTH)))])]
+        );
+    }
+
+    #[test]
+    fn unmatched_delimiters_stay_text_quickly() {
+        for input in ["*a ", "**a ", "_a ", "[a ", "[a](", "`"] {
+            let input = input.repeat(50_000);
+            assert_eq!(parse_inline(&input).len(), 1, "{:?}", &input[..20]);
+        }
+        let nested = format!("{}a{}", "*_".repeat(1000), "_*".repeat(1000));
+        assert!(!parse_inline(&nested).is_empty());
+    }
+}
@@ -0,0 +1,107 @@
+//! What links in messages point to, and which of them may be shown inline.
+//!
+//! Showing an image or a video makes everyone's browser fetch it, so only links on the hosts
+//! below are embedded; anything else stays a plain link.
+
+/// Schemes inline media may be loaded over.
+const INLINE_SCHEMES: &[&str] = &["https://"];
+/// Hosts inline media may be loaded from, along with their subdomains.
+const INLINE_HOSTS: &[&str] = &[
+    "giphy.com",
+    "tenor.com",
+    "imgur.com",
+    "upload.wikimedia.org",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Media {
+    Image,
+    Video,
+}
+
+/// The kind of file `url` points to, going by the extension of its path.
+pub fn detect(url: &str) -> Option<Media> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = rest.split(['?', '#']).next().unwrap_or_default();
+    let (_, file) = path.split_once('/')?;
+    let (_, extension) = file.rsplit('/').next()?.rsplit_once('.')?;
+    match extension.to_ascii_lowercase().as_str() {
+        "png" | "jpg" | "jpeg" | "webp" | "gif" => Some(Media::Image),
+        "mp4" => Some(Media::Video),
+        _ => None,
+    }
+}
+
+/// The lowercase host of a web address. Addresses with credentials in them have none, as
+/// `https://giphy.com@example.org/` really goes to example.org.
+pub fn host(url: &str) -> Option<String> {
+    let lower = url.to_ascii_lowercase();
+    let rest = ["http://", "https://"]
+        .iter()
+        .find_map(|scheme| lower.strip_prefix(scheme))?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if authority.contains(['@', '\\']) {
+        return None;
+    }
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => authority,
+    };
+    let host = host.trim_end_matches('.');
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+/// What `url` may be shown as in place, if it's an image or a video on an allowed host.
+pub fn inline(url: &str) -> Option<Media> {
+    let lower = url.to_ascii_lowercase();
+    if !INLINE_SCHEMES.iter().any(|scheme| lower.starts_with(scheme)) {
+        return None;
+    }
+    let host = host(url)?;
+    let allowed = INLINE_HOSTS
+        .iter()
+        .any(|allowed| host == *allowed || host.ends_with(&format!(".{}", allowed)));
+    if allowed {
+        detect(url)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_media_by_extension() {
+        assert_eq!(detect("https://a.org/cat.GIF"), Some(Media::Image));
+        assert_eq!(detect("https://a.org/x/cat.webp?size=2#top"), Some(Media::Image));
+        assert_eq!(detect("https://a.org/clip.mp4"), Some(Media::Video));
+        assert_eq!(detect("https://a.org/page.html"), None);
+        assert_eq!(detect("https://a.org/dir.png/"), None);
+        assert_eq!(detect("https://cat.gif"), None);
+        assert_eq!(detect("https://a.org/?file=cat.gif"), None);
+        assert_eq!(detect("just some text ending in .gif"), None);
+    }
+
+    #[test]
+    fn hosts_ignore_ports_and_refuse_credentials() {
+        assert_eq!(host("https://Media.Giphy.com:443/x"), Some("media.giphy.com".into()));
+        assert_eq!(host("http://imgur.com?x"), Some("imgur.com".into()));
+        assert_eq!(host("https://giphy.com@evil.example/x.gif"), None);
+        assert_eq!(host("ftp://giphy.com/x.gif"), None);
+        assert_eq!(host("https:///x.gif"), None);
+    }
+
+    #[test]
+    fn only_allowed_hosts_are_inlined() {
+        assert_eq!(inline("https://media.giphy.com/media/x/giphy.gif"), Some(Media::Image));
+        assert_eq!(inline("https://i.imgur.com/abc.mp4"), Some(Media::Video));
+        assert_eq!(inline("https://upload.wikimedia.org/a/b.jpg"), Some(Media::Image));
+        assert_eq!(inline("http://i.imgur.com/abc.png"), None);
+        assert_eq!(inline("https://notimgur.com/abc.png"), None);
+        assert_eq!(inline("https://imgur.com.evil.example/abc.png"), None);
+        assert_eq!(inline("https://example.org/abc.png"), None);
+        assert_eq!(inline("https://i.imgur.com/gallery"), None);
+    }
+}
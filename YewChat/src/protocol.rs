@@ -0,0 +1,916 @@
+//! Frames exchanged with `SimpleWebsocketServer`.
+//!
+//! Every frame is a JSON object whose `messageType` field selects the variant. The remaining
+//! field names (`data`, `dataArray`) are the ones the server has always used, so the enums
+//! below describe the existing wire format rather than a new one.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+/// `messageType` values [`ServerMessage`] knows how to decode.
+const SERVER_MESSAGE_TYPES: &[&str] = &[
+    "authOk",
+    "authRejected",
+    "registerAck",
+    "registerRejected",
+    "users",
+    "rooms",
+    "message",
+    "direct",
+    "directRejected",
+    "messageRejected",
+    "history",
+    "update",
+    "typing",
+    "reads",
+    "presence",
+    "profile",
+    "preview",
+];
+
+/// Room every user is placed in when registering. It can't be left.
+pub const DEFAULT_ROOM: &str = "general";
+/// Longest room id the server accepts.
+const MAX_ROOM_LEN: usize = 32;
+
+const MIN_USERNAME_LEN: usize = 2;
+const MAX_USERNAME_LEN: usize = 20;
+/// Names nobody may register, compared case-insensitively.
+const RESERVED_USERNAMES: &[&str] = &["admin", "server", "system", "you", "initial"];
+
+/// Longest message text the server accepts, in UTF-16 code units as the browser counts them.
+pub const MAX_MESSAGE_LEN: usize = 4000;
+pub const MAX_DISPLAY_NAME_LEN: usize = 32;
+pub const MAX_STATUS_TEXT_LEN: usize = 80;
+pub const MAX_PRONOUNS_LEN: usize = 20;
+pub const MAX_AVATAR_SEED_LEN: usize = 32;
+/// Dicebear styles users can pick for their avatar when built with the `dicebear` feature; the
+/// first one is the default.
+pub const AVATAR_STYLES: &[&str] = &[
+    "adventurer-neutral",
+    "bottts-neutral",
+    "fun-emoji",
+    "identicon",
+    "initials",
+    "pixel-art",
+    "thumbs",
+];
+
+/// Frames sent from the browser to the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "messageType", rename_all = "camelCase")]
+pub enum ClientMessage {
+    /// Logs the connection in as `data`, with either a password or a session token from
+    /// [`ServerMessage::AuthOk`]. The first password used for a nick claims it.
+    Auth {
+        data: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    /// Claims the nick this connection is logged in as.
+    Register { data: String },
+    /// Gives up the nick claimed with `Register`, e.g. when logging out.
+    Unregister,
+    /// Adds the registered user to a room, creating it if needed.
+    Join { room: String },
+    /// Removes the registered user from a room.
+    Leave { room: String },
+    /// A chat message from the registered user to one of their rooms. The server echoes
+    /// `nonce` back in [`MessageData::nonce`]; `parent` is the id of the message replied to.
+    Message {
+        data: String,
+        room: String,
+        nonce: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent: Option<u64>,
+    },
+    /// A private message to a single registered user, with a `nonce` and `parent` like
+    /// [`Self::Message`].
+    Direct {
+        data: String,
+        to: String,
+        nonce: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent: Option<u64>,
+    },
+    /// Asks for the page of `room` history right before the message with id `before`.
+    HistoryRequest { room: String, before: u64 },
+    /// Replaces the text of one of our own messages.
+    Edit { id: u64, data: String },
+    /// Deletes one of our own messages, leaving a tombstone in its place.
+    Delete { id: u64 },
+    /// Adds our `emoji` reaction to a message, or takes it back if we already reacted so.
+    React { id: u64, emoji: String },
+    /// We are composing a message in `room`, or to `to`. Repeated while typing goes on, as the
+    /// server gives up on it after a few seconds without one.
+    TypingStart {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        room: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+    },
+    /// We stopped composing in the conversation of an earlier [`Self::TypingStart`].
+    TypingStop {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        room: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+    },
+    /// We have seen everything up to the message `id` in `room`, or from `to`.
+    Read {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        room: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+        id: u64,
+    },
+    /// How available we are; every window starts out [`Presence::Online`].
+    Presence { status: Presence },
+    /// Replaces our profile.
+    UpdateProfile { profile: Profile },
+    /// Asks the server for the title and description of the page at `url`, for a preview card.
+    PreviewRequest { url: String },
+}
+
+/// Frames sent from the server to the browser.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "messageType", rename_all = "camelCase")]
+pub enum ServerMessage {
+    /// Logged in as `data`, spelled as the nick was first claimed. `token` logs in again later.
+    AuthOk { data: String, token: String },
+    /// The credentials from [`ClientMessage::Auth`] were wrong or the token has expired.
+    AuthRejected { reason: String },
+    /// The nick from [`ClientMessage::Register`] is now ours.
+    RegisterAck { data: String },
+    /// The nick from [`ClientMessage::Register`] was refused, e.g. because we aren't logged in.
+    RegisterRejected { reason: String },
+    /// Nicks of everyone currently registered.
+    Users {
+        #[serde(rename = "dataArray", default)]
+        data_array: Vec<String>,
+    },
+    /// Every room that currently has members, plus [`DEFAULT_ROOM`].
+    Rooms {
+        #[serde(default)]
+        rooms: Vec<RoomInfo>,
+    },
+    /// A chat message, sent to every member of its room including the sender.
+    Message {
+        #[serde(deserialize_with = "inline_or_encoded")]
+        data: MessageData,
+    },
+    /// A page of a room's history, oldest first. Sent after joining a room and in reply to
+    /// [`ClientMessage::HistoryRequest`].
+    History {
+        room: String,
+        #[serde(default)]
+        messages: Vec<MessageData>,
+        /// Whether older messages than the ones in this page exist.
+        #[serde(rename = "hasMore", default)]
+        has_more: bool,
+    },
+    /// A private message, sent to the recipient and echoed to the sender.
+    Direct {
+        #[serde(deserialize_with = "inline_or_encoded")]
+        data: MessageData,
+    },
+    /// Our private message to `to` wasn't delivered, e.g. because they are offline. `nonce` is
+    /// the one it was sent with.
+    DirectRejected {
+        to: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        reason: String,
+    },
+    /// Our room message wasn't accepted, e.g. because we aren't in the room. `nonce` is the
+    /// one it was sent with.
+    MessageRejected {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        reason: String,
+    },
+    /// The new state of an edited, deleted or reacted to message, sent to everyone who got the
+    /// original.
+    Update { data: MessageData },
+    /// How far everyone has read in `room`, or in our direct messages with `peer`, by nick.
+    /// Sent after registering for every conversation we take part in, after the history of
+    /// a joined room, and whenever somebody reads further.
+    Reads {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        room: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer: Option<String>,
+        #[serde(default)]
+        positions: BTreeMap<String, u64>,
+    },
+    /// `data` became `status`. Offline users come with the time they left, if the server saw it.
+    Presence {
+        data: String,
+        status: Presence,
+        #[serde(rename = "lastSeen", default, skip_serializing_if = "Option::is_none")]
+        last_seen: Option<u64>,
+    },
+    /// The profile of `data`. Sent after registering for everyone who has one, and whenever
+    /// somebody changes theirs.
+    Profile { data: String, profile: Profile },
+    /// Whether `data` is composing a message in `room`, or to us if `room` is missing.
+    Typing {
+        data: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        room: Option<String>,
+        active: bool,
+    },
+    /// Answers [`ClientMessage::PreviewRequest`]; without `preview` when the page couldn't be
+    /// fetched or has nothing to show.
+    Preview {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preview: Option<LinkPreview>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageData {
+    /// Assigned by the server, increasing over time. Servers without history send none (0).
+    #[serde(default)]
+    pub id: u64,
+    pub from: String,
+    pub message: String,
+    /// Milliseconds since the Unix epoch, stamped by the server.
+    #[serde(default)]
+    pub time: u64,
+    /// Messages from servers without rooms all belong to [`DEFAULT_ROOM`].
+    #[serde(default = "default_room")]
+    pub room: String,
+    /// Recipient of a direct message; `None` for room messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// Picked by the sender's client so it can recognise the echo of its own message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// When the text was last changed with [`ClientMessage::Edit`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited: Option<u64>,
+    /// Deleted by its sender; the server clears `message`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    /// In the order the emojis were first used.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<Reaction>,
+    /// Id of the message in the same conversation this one replies to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<u64>,
+}
+
+/// Everyone who reacted to a message with the same emoji.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    #[serde(default)]
+    pub users: Vec<String>,
+}
+
+impl MessageData {
+    /// Sent by this client but not echoed by the server yet.
+    pub fn is_pending(&self) -> bool {
+        self.id == 0 && self.nonce.is_some()
+    }
+}
+
+/// How available a user is. Users choose between the first three; the server reports the
+/// others as offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Presence {
+    Online,
+    Away,
+    DoNotDisturb,
+    Offline,
+}
+
+impl Presence {
+    pub const CHOICES: [Presence; 3] = [Presence::Online, Presence::Away, Presence::DoNotDisturb];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Presence::Online => "Online",
+            Presence::Away => "Away",
+            Presence::DoNotDisturb => "Do not disturb",
+            Presence::Offline => "Offline",
+        }
+    }
+
+    /// What to tell the others when `self` was chosen: being idle only turns online into away.
+    pub fn effective(self, idle: bool) -> Presence {
+        match self {
+            Presence::Online if idle => Presence::Away,
+            other => other,
+        }
+    }
+}
+
+/// What users tell about themselves. Empty fields fall back to the nick and the default
+/// avatar.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Profile {
+    pub display_name: String,
+    pub status_text: String,
+    pub pronouns: String,
+    /// Drawn instead of the nick, see [`crate::avatar`].
+    pub avatar_seed: String,
+    /// One of [`AVATAR_STYLES`].
+    pub avatar_style: String,
+}
+
+impl Profile {
+    pub fn display_name<'a>(&'a self, nick: &'a str) -> &'a str {
+        if self.display_name.is_empty() {
+            nick
+        } else {
+            &self.display_name
+        }
+    }
+
+    /// Trimmed and cut to the lengths the server keeps.
+    pub fn normalized(&self) -> Profile {
+        let clip = |text: &str, max: usize| text.trim().chars().take(max).collect::<String>();
+        Profile {
+            display_name: clip(&self.display_name, MAX_DISPLAY_NAME_LEN),
+            status_text: clip(&self.status_text, MAX_STATUS_TEXT_LEN),
+            pronouns: clip(&self.pronouns, MAX_PRONOUNS_LEN),
+            avatar_seed: clip(&self.avatar_seed, MAX_AVATAR_SEED_LEN),
+            avatar_style: if AVATAR_STYLES.contains(&self.avatar_style.as_str()) {
+                self.avatar_style.clone()
+            } else {
+                String::new()
+            },
+        }
+    }
+}
+
+/// What the server found out about a linked page.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LinkPreview {
+    pub title: String,
+    pub description: String,
+    /// The page's picture, shown only when [`crate::media::inline`] allows it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// Falls back to the host name.
+    pub site_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub id: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+fn default_room() -> String {
+    DEFAULT_ROOM.to_string()
+}
+
+/// Why a username can't be registered, checked before asking the server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UsernameError {
+    TooShort,
+    TooLong,
+    InvalidCharacter(char),
+    Reserved,
+}
+
+impl fmt::Display for UsernameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsernameError::TooShort => {
+                write!(f, "Usernames need at least {} characters.", MIN_USERNAME_LEN)
+            }
+            UsernameError::TooLong => {
+                write!(f, "Usernames can have at most {} characters.", MAX_USERNAME_LEN)
+            }
+            UsernameError::InvalidCharacter(c) => write!(
+                f,
+                "\"{}\" is not allowed; use letters, digits, \".\", \"_\" or \"-\".",
+                c
+            ),
+            UsernameError::Reserved => write!(f, "That username is reserved."),
+        }
+    }
+}
+
+/// The rules the server applies to nicks, so obviously invalid ones never leave the browser.
+/// Whether a nick is taken is only known to the server.
+pub fn validate_username(name: &str) -> Result<(), UsernameError> {
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')))
+    {
+        return Err(UsernameError::InvalidCharacter(c));
+    }
+    if name.len() < MIN_USERNAME_LEN {
+        return Err(UsernameError::TooShort);
+    }
+    if name.len() > MAX_USERNAME_LEN {
+        return Err(UsernameError::TooLong);
+    }
+    if RESERVED_USERNAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(name))
+    {
+        return Err(UsernameError::Reserved);
+    }
+    Ok(())
+}
+
+/// Turns a user-entered room name into an id the server accepts: lowercase ASCII letters,
+/// digits and dashes, not starting with a dash. Whitespace becomes dashes.
+pub fn room_id(name: &str) -> Option<String> {
+    let id: String = name
+        .trim()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-");
+    let valid = !id.is_empty()
+        && id.len() <= MAX_ROOM_LEN
+        && !id.starts_with('-')
+        && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    valid.then_some(id)
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("client frames always serialize")
+    }
+}
+
+/// Why a frame from the server could not be turned into a [`ServerMessage`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The frame is not a JSON document.
+    Malformed(String),
+    /// The frame has no string `messageType` field.
+    MissingType,
+    /// The server sent a `messageType` this client doesn't know (yet).
+    UnknownType(String),
+    /// The `messageType` is known but the rest of the frame doesn't match it.
+    InvalidPayload { message_type: String, reason: String },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Malformed(reason) => write!(f, "malformed frame: {}", reason),
+            DecodeError::MissingType => write!(f, "frame has no messageType"),
+            DecodeError::UnknownType(message_type) => {
+                write!(f, "unknown messageType \"{}\"", message_type)
+            }
+            DecodeError::InvalidPayload {
+                message_type,
+                reason,
+            } => write!(f, "invalid \"{}\" frame: {}", message_type, reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl ServerMessage {
+    pub fn decode(s: &str) -> Result<Self, DecodeError> {
+        let value: Value =
+            serde_json::from_str(s).map_err(|e| DecodeError::Malformed(e.to_string()))?;
+        let message_type = value
+            .get("messageType")
+            .and_then(Value::as_str)
+            .ok_or(DecodeError::MissingType)?
+            .to_string();
+        if !SERVER_MESSAGE_TYPES.contains(&message_type.as_str()) {
+            return Err(DecodeError::UnknownType(message_type));
+        }
+        serde_json::from_value(value).map_err(|e| DecodeError::InvalidPayload {
+            message_type,
+            reason: e.to_string(),
+        })
+    }
+}
+
+/// Older servers sent payloads as a JSON document encoded inside a JSON string; newer ones
+/// inline the object. Accept both.
+fn inline_or_encoded<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr<T> {
+        Encoded(String),
+        Inline(T),
+    }
+
+    match Repr::<T>::deserialize(deserializer)? {
+        Repr::Encoded(s) => serde_json::from_str(&s).map_err(serde::de::Error::custom),
+        Repr::Inline(value) => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message() -> MessageData {
+        MessageData {
+            id: 42,
+            from: "alice".into(),
+            message: "hello".into(),
+            time: 1_700_000_000_000,
+            room: "rust".into(),
+            to: None,
+            nonce: None,
+            edited: None,
+            deleted: false,
+            reactions: vec![],
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn client_frames_use_the_server_field_names() {
+        let frame = ClientMessage::Register { data: "alice".into() };
+        let value: serde_json::Value = serde_json::from_str(&frame.encode()).unwrap();
+        assert_eq!(value, json!({ "messageType": "register", "data": "alice" }));
+
+        let frame = ClientMessage::Auth {
+            data: "alice".into(),
+            password: None,
+            token: Some("t".into()),
+        };
+        let value: serde_json::Value = serde_json::from_str(&frame.encode()).unwrap();
+        assert_eq!(
+            value,
+            json!({ "messageType": "auth", "data": "alice", "token": "t" })
+        );
+    }
+
+    #[test]
+    fn client_frames_round_trip() {
+        let frames = vec![
+            ClientMessage::Auth {
+                data: "alice".into(),
+                password: Some("hunter2".into()),
+                token: None,
+            },
+            ClientMessage::Auth {
+                data: "alice".into(),
+                password: None,
+                token: Some("alice.1.abc".into()),
+            },
+            ClientMessage::Register { data: "alice".into() },
+            ClientMessage::Unregister,
+            ClientMessage::Join { room: "rust".into() },
+            ClientMessage::Leave { room: "rust".into() },
+            ClientMessage::Message {
+                data: "hi there".into(),
+                room: "rust".into(),
+                nonce: "n1".into(),
+                parent: Some(41),
+            },
+            ClientMessage::Direct {
+                data: "psst".into(),
+                to: "bob".into(),
+                nonce: "n2".into(),
+                parent: None,
+            },
+            ClientMessage::HistoryRequest {
+                room: "rust".into(),
+                before: 42,
+            },
+            ClientMessage::Edit {
+                id: 42,
+                data: "hi there!".into(),
+            },
+            ClientMessage::Delete { id: 42 },
+            ClientMessage::React {
+                id: 42,
+                emoji: "🎉".into(),
+            },
+            ClientMessage::TypingStart {
+                room: Some("rust".into()),
+                to: None,
+            },
+            ClientMessage::TypingStop {
+                room: None,
+                to: Some("bob".into()),
+            },
+            ClientMessage::Read {
+                room: Some("rust".into()),
+                to: None,
+                id: 42,
+            },
+            ClientMessage::Presence {
+                status: Presence::DoNotDisturb,
+            },
+            ClientMessage::UpdateProfile {
+                profile: Profile {
+                    display_name: "Alice".into(),
+                    pronouns: "she/her".into(),
+                    ..Profile::default()
+                },
+            },
+            ClientMessage::PreviewRequest {
+                url: "https://yew.rs".into(),
+            },
+        ];
+        for frame in frames {
+            let decoded: ClientMessage = serde_json::from_str(&frame.encode()).unwrap();
+            assert_eq!(decoded, frame);
+        }
+    }
+
+    #[test]
+    fn server_frames_round_trip() {
+        let frames = vec![
+            ServerMessage::AuthOk {
+                data: "alice".into(),
+                token: "alice.1.abc".into(),
+            },
+            ServerMessage::AuthRejected {
+                reason: "Wrong username or password.".into(),
+            },
+            ServerMessage::RegisterAck {
+                data: "alice".into(),
+            },
+            ServerMessage::RegisterRejected {
+                reason: "taken".into(),
+            },
+            ServerMessage::Users {
+                data_array: vec!["alice".into(), "bob".into()],
+            },
+            ServerMessage::Rooms {
+                rooms: vec![RoomInfo {
+                    id: "rust".into(),
+                    members: vec!["alice".into()],
+                }],
+            },
+            ServerMessage::Message { data: message() },
+            ServerMessage::Message {
+                data: MessageData {
+                    parent: Some(41),
+                    ..message()
+                },
+            },
+            ServerMessage::Direct {
+                data: MessageData {
+                    to: Some("bob".into()),
+                    nonce: Some("n2".into()),
+                    ..message()
+                },
+            },
+            ServerMessage::DirectRejected {
+                to: "bob".into(),
+                nonce: Some("n2".into()),
+                reason: "bob is offline.".into(),
+            },
+            ServerMessage::MessageRejected {
+                nonce: Some("n3".into()),
+                reason: "You are not in #rust.".into(),
+            },
+            ServerMessage::History {
+                room: "rust".into(),
+                messages: vec![message()],
+                has_more: true,
+            },
+            ServerMessage::Update {
+                data: MessageData {
+                    message: String::new(),
+                    edited: Some(1_700_000_060_000),
+                    deleted: true,
+                    ..message()
+                },
+            },
+            ServerMessage::Update {
+                data: MessageData {
+                    reactions: vec![Reaction {
+                        emoji: "👍".into(),
+                        users: vec!["alice".into(), "bob".into()],
+                    }],
+                    ..message()
+                },
+            },
+            ServerMessage::Typing {
+                data: "bob".into(),
+                room: Some("rust".into()),
+                active: true,
+            },
+            ServerMessage::Typing {
+                data: "bob".into(),
+                room: None,
+                active: false,
+            },
+            ServerMessage::Reads {
+                room: None,
+                peer: Some("bob".into()),
+                positions: BTreeMap::from([("alice".into(), 40), ("bob".into(), 42)]),
+            },
+            ServerMessage::Presence {
+                data: "bob".into(),
+                status: Presence::Offline,
+                last_seen: Some(1_700_000_000_000),
+            },
+            ServerMessage::Profile {
+                data: "bob".into(),
+                profile: Profile {
+                    status_text: "Out for lunch".into(),
+                    avatar_seed: "otter".into(),
+                    avatar_style: "thumbs".into(),
+                    ..Profile::default()
+                },
+            },
+            ServerMessage::Preview {
+                url: "https://yew.rs".into(),
+                preview: Some(LinkPreview {
+                    title: "Yew".into(),
+                    description: "A framework for making client-side single-page apps".into(),
+                    image: None,
+                    site_name: "yew.rs".into(),
+                }),
+            },
+            ServerMessage::Preview {
+                url: "https://example.org/404".into(),
+                preview: None,
+            },
+        ];
+        for frame in frames {
+            let encoded = serde_json::to_string(&frame).unwrap();
+            assert_eq!(ServerMessage::decode(&encoded).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn decodes_legacy_string_encoded_message() {
+        let inner = json!({
+            "from": "alice",
+            "message": "hello",
+            "id": 42,
+            "time": 1_700_000_000_000u64,
+            "room": "rust",
+        });
+        let frame = json!({ "messageType": "message", "data": inner.to_string() }).to_string();
+        assert_eq!(
+            ServerMessage::decode(&frame).unwrap(),
+            ServerMessage::Message { data: message() }
+        );
+    }
+
+    #[test]
+    fn decodes_legacy_message_without_time() {
+        let frame = r#"{"messageType":"message","data":"{\"from\":\"bob\",\"message\":\"yo\"}"}"#;
+        match ServerMessage::decode(frame).unwrap() {
+            ServerMessage::Message { data } => {
+                assert_eq!(data.from, "bob");
+                assert_eq!(data.time, 0);
+                assert_eq!(data.room, DEFAULT_ROOM);
+            }
+            other => panic!("unexpected frame {:?}", other),
+        }
+    }
+
+    #[test]
+    fn typing_frames_use_camel_case_types() {
+        let frame = ClientMessage::TypingStart {
+            room: None,
+            to: Some("bob".into()),
+        };
+        let value: serde_json::Value = serde_json::from_str(&frame.encode()).unwrap();
+        assert_eq!(value, json!({ "messageType": "typingStart", "to": "bob" }));
+    }
+
+    #[test]
+    fn presence_uses_camel_case_states() {
+        let frame = ServerMessage::decode(
+            r#"{"messageType":"presence","data":"bob","status":"doNotDisturb"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            frame,
+            ServerMessage::Presence {
+                data: "bob".into(),
+                status: Presence::DoNotDisturb,
+                last_seen: None,
+            }
+        );
+        assert_eq!(Presence::Online.effective(true), Presence::Away);
+        assert_eq!(Presence::DoNotDisturb.effective(true), Presence::DoNotDisturb);
+        assert_eq!(Presence::Online.effective(false), Presence::Online);
+    }
+
+    #[test]
+    fn profiles_fall_back_to_the_nick() {
+        let profile: Profile = serde_json::from_str(r#"{"avatarSeed":"a b"}"#).unwrap();
+        assert_eq!(profile.display_name("bob"), "bob");
+        let edited = Profile {
+            display_name: format!("  {}  ", "x".repeat(40)),
+            avatar_style: "unknown".into(),
+            ..profile
+        }
+        .normalized();
+        assert_eq!(edited.display_name, "x".repeat(MAX_DISPLAY_NAME_LEN));
+        assert_eq!(edited.avatar_style, "");
+        assert_eq!(edited.display_name("bob"), edited.display_name);
+    }
+
+    #[test]
+    fn history_request_uses_camel_case_type() {
+        let frame = ClientMessage::HistoryRequest {
+            room: "general".into(),
+            before: 7,
+        };
+        let value: serde_json::Value = serde_json::from_str(&frame.encode()).unwrap();
+        assert_eq!(
+            value,
+            json!({ "messageType": "historyRequest", "room": "general", "before": 7 })
+        );
+    }
+
+    #[test]
+    fn rejects_frames_that_are_not_json() {
+        assert!(matches!(
+            ServerMessage::decode("not json"),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_frames_without_message_type() {
+        assert_eq!(
+            ServerMessage::decode(r#"{"data":"x"}"#),
+            Err(DecodeError::MissingType)
+        );
+        assert_eq!(
+            ServerMessage::decode(r#"{"messageType":7}"#),
+            Err(DecodeError::MissingType)
+        );
+    }
+
+    #[test]
+    fn reports_unknown_message_types() {
+        assert_eq!(
+            ServerMessage::decode(r#"{"messageType":"shrug","data":"x"}"#),
+            Err(DecodeError::UnknownType("shrug".into()))
+        );
+    }
+
+    #[test]
+    fn reports_invalid_payloads_of_known_types() {
+        match ServerMessage::decode(r#"{"messageType":"message","data":"{\"from\":1}"}"#) {
+            Err(DecodeError::InvalidPayload { message_type, .. }) => {
+                assert_eq!(message_type, "message")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn usernames_are_validated() {
+        assert_eq!(validate_username("alice"), Ok(()));
+        assert_eq!(validate_username("a.b_c-9"), Ok(()));
+        assert_eq!(validate_username("a"), Err(UsernameError::TooShort));
+        assert_eq!(validate_username(&"a".repeat(21)), Err(UsernameError::TooLong));
+        assert_eq!(
+            validate_username("bob smith"),
+            Err(UsernameError::InvalidCharacter(' '))
+        );
+        assert_eq!(
+            validate_username("zoë"),
+            Err(UsernameError::InvalidCharacter('ë'))
+        );
+        assert_eq!(validate_username("Admin"), Err(UsernameError::Reserved));
+    }
+
+    #[test]
+    fn room_ids_are_normalized() {
+        assert_eq!(room_id("  Rust Lang "), Some("rust-lang".into()));
+        assert_eq!(room_id("general"), Some("general".into()));
+        assert_eq!(room_id(""), None);
+        assert_eq!(room_id("-dash"), None);
+        assert_eq!(room_id("c++"), None);
+        assert_eq!(room_id(&"a".repeat(33)), None);
+    }
+
+    #[test]
+    fn users_without_data_array_is_empty() {
+        assert_eq!(
+            ServerMessage::decode(r#"{"messageType":"users"}"#).unwrap(),
+            ServerMessage::Users { data_array: vec![] }
+        );
+    }
+}
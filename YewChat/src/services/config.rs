@@ -0,0 +1,100 @@
+use web_sys::UrlSearchParams;
+
+/// Query parameter that overrides the websocket endpoint, e.g. `?server=wss://chat.example.com`.
+const SERVER_QUERY_PARAM: &str = "server";
+/// `<meta name="yewchat-server" content="...">` in `index.html`.
+const SERVER_META_SELECTOR: &str = "meta[name='yewchat-server']";
+
+/// Settings resolved once at startup from the page the app is served from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppConfig {
+    pub server_url: String,
+}
+
+impl AppConfig {
+    /// The query parameter wins over the meta tag; without either the server is assumed to
+    /// live at the page origin, using `wss` when the page itself was served over https.
+    pub fn from_page() -> Self {
+        let window = web_sys::window().expect("no global `window` exists");
+        let location = window.location();
+
+        let from_query = location
+            .search()
+            .ok()
+            .and_then(|search| UrlSearchParams::new_with_str(&search).ok())
+            .and_then(|params| params.get(SERVER_QUERY_PARAM));
+        let from_meta = window
+            .document()
+            .and_then(|doc| doc.query_selector(SERVER_META_SELECTOR).ok().flatten())
+            .and_then(|meta| meta.get_attribute("content"));
+
+        let server_url = resolve_server_url(
+            from_query,
+            from_meta,
+            &location.protocol().unwrap_or_default(),
+            &location.host().unwrap_or_default(),
+        );
+        log::debug!("Using server {}", server_url);
+
+        Self { server_url }
+    }
+}
+
+/// The first endpoint given, from the query then the meta tag, ignoring blank ones. Absolute
+/// endpoints are used as is, ones starting with `/` are taken relative to the page host.
+fn resolve_server_url(
+    from_query: Option<String>,
+    from_meta: Option<String>,
+    page_protocol: &str,
+    page_host: &str,
+) -> String {
+    let scheme = if page_protocol == "https:" { "wss" } else { "ws" };
+    let configured = [from_query, from_meta]
+        .into_iter()
+        .flatten()
+        .map(|url| url.trim().to_string())
+        .find(|url| !url.is_empty());
+    match configured.as_deref() {
+        Some(path) if path.starts_with('/') => format!("{}://{}{}", scheme, page_host, path),
+        Some(url) => url.to_string(),
+        None => format!("{}://{}", scheme, page_host),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(configured: Option<&str>, protocol: &str) -> String {
+        resolve_server_url(None, configured.map(String::from), protocol, "chat.example.com:8000")
+    }
+
+    #[test]
+    fn the_query_wins_over_the_meta_tag() {
+        let resolve = |query: &str, meta: &str| {
+            resolve_server_url(Some(query.into()), Some(meta.into()), "http:", "a.org")
+        };
+        assert_eq!(resolve("ws://query.org", "ws://meta.org"), "ws://query.org");
+        assert_eq!(resolve("", "ws://meta.org"), "ws://meta.org");
+        assert_eq!(resolve("/ws", ""), "ws://a.org/ws");
+    }
+
+    #[test]
+    fn configured_urls_are_used_as_they_are() {
+        assert_eq!(resolve(Some("ws://127.0.0.1:8080"), "https:"), "ws://127.0.0.1:8080");
+        assert_eq!(resolve(Some(" wss://a.org/ws "), "http:"), "wss://a.org/ws");
+    }
+
+    #[test]
+    fn paths_are_taken_relative_to_the_page_host() {
+        assert_eq!(resolve(Some("/ws"), "http:"), "ws://chat.example.com:8000/ws");
+        assert_eq!(resolve(Some("/ws"), "https:"), "wss://chat.example.com:8000/ws");
+    }
+
+    #[test]
+    fn nothing_configured_means_the_page_origin() {
+        assert_eq!(resolve(None, "http:"), "ws://chat.example.com:8000");
+        assert_eq!(resolve(Some(""), "https:"), "wss://chat.example.com:8000");
+        assert_eq!(resolve(Some("  "), "http:"), "ws://chat.example.com:8000");
+    }
+}
@@ -1,2 +1,4 @@
+pub mod config;
 pub mod websocket;
-pub mod event_bus;
\ No newline at end of file
+pub mod event_bus;
+pub mod storage;
\ No newline at end of file
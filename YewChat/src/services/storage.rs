@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use gloo_storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{MessageData, Presence, Profile};
+
+const USERNAME_KEY: &str = "yewchat.username";
+const TOKEN_KEY: &str = "yewchat.token";
+const PRESENCE_KEY: &str = "yewchat.presence";
+const PROFILE_KEY: &str = "yewchat.profile";
+const LINK_PREVIEWS_KEY: &str = "yewchat.linkPreviews";
+const MESSAGES_KEY_PREFIX: &str = "yewchat.messages.";
+/// Most recent messages kept per room and per direct message thread.
+const CACHED_PER_CONVERSATION: usize = 100;
+
+/// Recent messages of one user, as persisted between page loads.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MessageCache {
+    #[serde(default)]
+    pub rooms: HashMap<String, Vec<MessageData>>,
+    #[serde(default)]
+    pub directs: HashMap<String, Vec<MessageData>>,
+}
+
+pub fn load_username() -> Option<String> {
+    LocalStorage::get(USERNAME_KEY).ok()
+}
+
+pub fn save_username(username: &str) {
+    if let Err(e) = LocalStorage::set(USERNAME_KEY, username) {
+        log::warn!("Could not persist username: {:?}", e);
+    }
+}
+
+/// The session token from the last login, used to log in again without a password.
+pub fn load_token() -> Option<String> {
+    LocalStorage::get(TOKEN_KEY).ok()
+}
+
+pub fn save_token(token: &str) {
+    if let Err(e) = LocalStorage::set(TOKEN_KEY, token) {
+        log::warn!("Could not persist session token: {:?}", e);
+    }
+}
+
+/// The presence the user last picked, if they picked one.
+pub fn load_presence() -> Option<Presence> {
+    LocalStorage::get(PRESENCE_KEY).ok()
+}
+
+pub fn save_presence(presence: Presence) {
+    if let Err(e) = LocalStorage::set(PRESENCE_KEY, presence) {
+        log::warn!("Could not persist presence: {:?}", e);
+    }
+}
+
+/// Our profile as the server last confirmed it, for the profile editor.
+pub fn load_profile() -> Option<Profile> {
+    LocalStorage::get(PROFILE_KEY).ok()
+}
+
+pub fn save_profile(profile: &Profile) {
+    if let Err(e) = LocalStorage::set(PROFILE_KEY, profile) {
+        log::warn!("Could not persist profile: {:?}", e);
+    }
+}
+
+/// Whether the user turned on preview cards for links.
+pub fn load_link_previews() -> Option<bool> {
+    LocalStorage::get(LINK_PREVIEWS_KEY).ok()
+}
+
+pub fn save_link_previews(enabled: bool) {
+    if let Err(e) = LocalStorage::set(LINK_PREVIEWS_KEY, enabled) {
+        log::warn!("Could not persist link preview setting: {:?}", e);
+    }
+}
+
+/// Forgets the stored session and settings but keeps the cached messages, e.g. when the session
+/// was refused.
+pub fn forget_session() {
+    LocalStorage::delete(USERNAME_KEY);
+    LocalStorage::delete(TOKEN_KEY);
+    LocalStorage::delete(PRESENCE_KEY);
+    LocalStorage::delete(PROFILE_KEY);
+    LocalStorage::delete(LINK_PREVIEWS_KEY);
+}
+
+/// Forgets the logged in user and everything cached for them.
+pub fn clear_session(username: &str) {
+    forget_session();
+    LocalStorage::delete(messages_key(username));
+}
+
+/// Cached messages of `username`; empty when nothing was cached or the cache is unreadable.
+pub fn load_messages(username: &str) -> MessageCache {
+    LocalStorage::get(messages_key(username)).unwrap_or_default()
+}
+
+pub fn save_messages(
+    username: &str,
+    rooms: &HashMap<String, Vec<MessageData>>,
+    directs: &HashMap<String, Vec<MessageData>>,
+) {
+    let cache = MessageCache {
+        rooms: recent(rooms),
+        directs: recent(directs),
+    };
+    if let Err(e) = LocalStorage::set(messages_key(username), cache) {
+        log::warn!("Could not cache messages: {:?}", e);
+    }
+}
+
+fn messages_key(username: &str) -> String {
+    format!("{}{}", MESSAGES_KEY_PREFIX, username)
+}
+
+/// The newest messages of every conversation. Messages still waiting for their echo are left
+/// out; after a reload nothing would ever confirm them.
+fn recent(buffers: &HashMap<String, Vec<MessageData>>) -> HashMap<String, Vec<MessageData>> {
+    buffers
+        .iter()
+        .map(|(key, messages)| {
+            let confirmed: Vec<&MessageData> = messages.iter().filter(|m| !m.is_pending()).collect();
+            let skip = confirmed.len().saturating_sub(CACHED_PER_CONVERSATION);
+            (key.clone(), confirmed.into_iter().skip(skip).cloned().collect::<Vec<_>>())
+        })
+        .filter(|(_, messages)| !messages.is_empty())
+        .collect()
+}
@@ -1,50 +1,245 @@
-use futures::{channel::mpsc::Sender, SinkExt, StreamExt};
-use reqwasm::websocket::{futures::WebSocket, Message};
-use yew_agent::Dispatched;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::task::Poll;
+
+use futures::{
+    channel::mpsc::{Receiver, Sender},
+    select, FutureExt, SinkExt, StreamExt,
+};
+use gloo_timers::future::TimeoutFuture;
+use reqwasm::websocket::{futures::WebSocket, Message, State};
+use serde::{Deserialize, Serialize};
+use yew_agent::{Dispatched, Dispatcher};
 use crate::services::event_bus::{EventBus, Request};
+use crate::time;
 
 use wasm_bindgen_futures::spawn_local;
 
+const BACKOFF_BASE_MS: u32 = 500;
+const BACKOFF_MAX_MS: u32 = 30_000;
+/// A connection attempt still not open after this long is given up on.
+const CONNECT_TIMEOUT_MS: u32 = 10_000;
+/// A connection has to stay open this long before reconnecting starts over from the shortest
+/// delay, so a server closing every connection right away is still backed off from.
+const STABLE_AFTER_MS: u64 = 5_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ConnectionState {
+    Connecting,
+    Open,
+    Reconnecting,
+    Closed,
+}
+
 pub struct WebsocketService {
     pub tx: Sender<String>,
+    state: Rc<Cell<ConnectionState>>,
 }
 
 impl WebsocketService {
-    pub fn new() -> Self {
-        let ws = WebSocket::open("ws://127.0.0.1:8080").unwrap();
-        let (mut write, mut read) = ws.split();
-        let (in_tx, mut in_rx) = futures::channel::mpsc::channel::<String>(1000);
-        let mut event_bus = EventBus::dispatcher();
-
-        spawn_local(async move {
-            while let Some(s) = in_rx.next().await {
-                log::debug!("Got event from channel! {}", s);
-                write.send(Message::Text(s)).await.unwrap();
+    /// Opens a connection to `url` and keeps it alive until `tx` is dropped.
+    ///
+    /// `handshake` is called every time a connection is established; the frames it returns are
+    /// sent before anything queued on `tx`, so the server learns who we are again after a
+    /// reconnect.
+    pub fn new<F>(url: &str, handshake: F) -> Self
+    where
+        F: Fn() -> Vec<String> + 'static,
+    {
+        let (in_tx, in_rx) = futures::channel::mpsc::channel::<String>(1000);
+        let state = Rc::new(Cell::new(ConnectionState::Connecting));
+        spawn_local(run(url.to_string(), in_rx, handshake, state.clone()));
+        Self { tx: in_tx, state }
+    }
+
+    /// The state most recently announced on the event bus, for components created after it.
+    pub fn state(&self) -> ConnectionState {
+        self.state.get()
+    }
+}
+
+impl PartialEq for WebsocketService {
+    fn eq(&self, other: &Self) -> bool {
+        self.tx.same_receiver(&other.tx)
+    }
+}
+
+fn announce(state: &Cell<ConnectionState>, new_state: ConnectionState) {
+    state.set(new_state);
+    EventBus::dispatcher().send(Request::ConnectionState(new_state));
+}
+
+enum Disconnect {
+    Lost,
+    Dropped,
+}
+
+async fn run<F>(
+    url: String,
+    mut in_rx: Receiver<String>,
+    handshake: F,
+    state: Rc<Cell<ConnectionState>>,
+) where
+    F: Fn() -> Vec<String>,
+{
+    let mut attempt = 0;
+    announce(&state, ConnectionState::Connecting);
+
+    loop {
+        let mut opened_at = None;
+        match connect(&url, &mut in_rx, &handshake, &state, &mut opened_at).await {
+            Disconnect::Dropped => break,
+            Disconnect::Lost => {
+                announce(&state, ConnectionState::Reconnecting);
+                let open_for = opened_at.map(|opened_at| time::now().saturating_sub(opened_at));
+                attempt = attempts_after_loss(attempt, open_for);
+                let delay = backoff_delay(attempt, js_sys::Math::random());
+                log::debug!("Reconnecting in {}ms (attempt {})", delay, attempt + 1);
+                TimeoutFuture::new(delay).await;
+                attempt = attempt.saturating_add(1);
             }
-        });
-
-        spawn_local(async move {
-            while let Some(msg) = read.next().await {
-                match msg {
-                    Ok(Message::Text(data)) => {
-                        log::debug!("From websocket: {}", data);
-                        event_bus.send(Request::EventBusMsg(data));
-                    }
-                    Ok(Message::Bytes(b)) => {
-                        let decoded = std::str::from_utf8(&b);
-                        if let Ok(val) = decoded {
-                            log::debug!("From websocket: {}", val);
-                            event_bus.send(Request::EventBusMsg(val.into()));
-                        }
-                    }
-                    Err(e) => {
+        }
+    }
+
+    log::debug!("WebSocket closed!");
+    announce(&state, ConnectionState::Closed);
+}
+
+/// Runs a single connection until it goes away, forwarding frames in both directions.
+async fn connect<F>(
+    url: &str,
+    in_rx: &mut Receiver<String>,
+    handshake: &F,
+    state: &Cell<ConnectionState>,
+    opened_at: &mut Option<u64>,
+) -> Disconnect
+where
+    F: Fn() -> Vec<String>,
+{
+    let mut ws = match WebSocket::open(url) {
+        Ok(ws) => ws,
+        Err(e) => {
+            log::error!("ws: {:?}", e);
+            return Disconnect::Lost;
+        }
+    };
+    let mut event_bus = EventBus::dispatcher();
+
+    // The sink becomes ready once the socket has left the `Connecting` state, but only opening
+    // wakes it up: a failed attempt shows on the stream instead, as an error or its end.
+    let settled = futures::future::poll_fn(|cx| {
+        loop {
+            if ws.poll_ready_unpin(cx).is_ready() {
+                return Poll::Ready(());
+            }
+            match ws.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(msg))) => forward(&mut event_bus, msg),
+                Poll::Ready(_) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    });
+    select! {
+        () = settled.fuse() => {},
+        () = TimeoutFuture::new(CONNECT_TIMEOUT_MS).fuse() => {
+            log::error!("ws: no connection after {}ms", CONNECT_TIMEOUT_MS);
+            return Disconnect::Lost;
+        }
+    }
+    if !matches!(ws.state(), State::Open) {
+        return Disconnect::Lost;
+    }
+
+    let (mut write, mut read) = ws.split();
+    for frame in handshake() {
+        if let Err(e) = write.send(Message::Text(frame)).await {
+            log::error!("ws: {:?}", e);
+            return Disconnect::Lost;
+        }
+    }
+
+    *opened_at = Some(time::now());
+    announce(state, ConnectionState::Open);
+
+    loop {
+        select! {
+            s = in_rx.next().fuse() => match s {
+                Some(s) => {
+                    log::debug!("Got event from channel! {}", s);
+                    if let Err(e) = write.send(Message::Text(s)).await {
                         log::error!("ws: {:?}", e);
+                        return Disconnect::Lost;
                     }
                 }
+                None => return Disconnect::Dropped,
+            },
+            msg = read.next().fuse() => match msg {
+                Some(Ok(msg)) => forward(&mut event_bus, msg),
+                Some(Err(e)) => {
+                    log::error!("ws: {:?}", e);
+                }
+                None => return Disconnect::Lost,
+            },
+        }
+    }
+}
+
+/// Hands a frame from the server to the components listening on the event bus.
+fn forward(event_bus: &mut Dispatcher<EventBus>, msg: Message) {
+    match msg {
+        Message::Text(data) => {
+            log::debug!("From websocket: {}", data);
+            event_bus.send(Request::EventBusMsg(data));
+        }
+        Message::Bytes(b) => {
+            if let Ok(val) = std::str::from_utf8(&b) {
+                log::debug!("From websocket: {}", val);
+                event_bus.send(Request::EventBusMsg(val.into()));
             }
-            log::debug!("WebSocket closed!");
-        });
+        }
+    }
+}
+
+/// Delay before the reconnect attempt following `attempt` failed ones.
+///
+/// The delay doubles with every attempt up to `BACKOFF_MAX_MS`; half of it is fixed and the
+/// other half scaled by `jitter` (expected in `0.0..1.0`) so clients that dropped together
+/// don't all come back at the same instant.
+fn backoff_delay(attempt: u32, jitter: f64) -> u32 {
+    let ceiling = BACKOFF_BASE_MS
+        .saturating_mul(1 << attempt.min(16))
+        .min(BACKOFF_MAX_MS);
+    let half = ceiling / 2;
+    half + (f64::from(half) * jitter.clamp(0.0, 1.0)) as u32
+}
 
-        Self { tx: in_tx }
+/// The failed attempts to go on counting from after losing a connection that was open for
+/// `open_for` milliseconds, or never opened: only one that lasted starts the count over.
+fn attempts_after_loss(attempt: u32, open_for: Option<u64>) -> u32 {
+    match open_for {
+        Some(open_for) if open_for >= STABLE_AFTER_MS => 0,
+        _ => attempt,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        assert_eq!(backoff_delay(0, 0.0), BACKOFF_BASE_MS / 2);
+        assert_eq!(backoff_delay(0, 1.0), BACKOFF_BASE_MS);
+        assert_eq!(backoff_delay(3, 1.0), BACKOFF_BASE_MS * 8);
+        assert_eq!(backoff_delay(40, 1.0), BACKOFF_MAX_MS);
+        assert_eq!(backoff_delay(40, 7.0), BACKOFF_MAX_MS);
+    }
+
+    #[test]
+    fn only_lasting_connections_reset_the_backoff() {
+        assert_eq!(attempts_after_loss(4, None), 4);
+        assert_eq!(attempts_after_loss(4, Some(0)), 4);
+        assert_eq!(attempts_after_loss(4, Some(STABLE_AFTER_MS - 1)), 4);
+        assert_eq!(attempts_after_loss(4, Some(STABLE_AFTER_MS)), 0);
+    }
+}
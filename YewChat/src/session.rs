@@ -0,0 +1,225 @@
+//! Logging in from the login form: the password is sent first, and the nick is registered
+//! once the server has accepted it.
+//!
+//! A login waits for answers that may never come when the connection drops, so it starts over
+//! on the next connection: the session token of the old one, if any, isn't used, as the server
+//! tied it to the socket that went away.
+
+use crate::protocol::{ClientMessage, ServerMessage};
+use crate::services::websocket::ConnectionState;
+
+/// A login waiting for the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Login {
+    name: String,
+    password: String,
+    /// The session token once the password was accepted; the nick isn't registered yet.
+    token: Option<String>,
+    /// Whether the connection went away while waiting.
+    interrupted: bool,
+}
+
+/// What happened while a login was waiting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Frame(Box<ServerMessage>),
+    Connection(ConnectionState),
+}
+
+/// Where a login is at after an [`Event`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Nothing the login waits for.
+    Ignored,
+    /// Still waiting, after sending `send`.
+    Waiting { login: Login, send: Vec<ClientMessage> },
+    /// Logged in as `name`, spelled the way the server knows it.
+    Done { name: String, token: String },
+    Failed(String),
+}
+
+impl Login {
+    /// A login as `name`, and the frame that starts it.
+    pub fn start(name: String, password: String) -> (Self, ClientMessage) {
+        let login = Self {
+            name,
+            password,
+            token: None,
+            interrupted: false,
+        };
+        let auth = login.auth();
+        (login, auth)
+    }
+
+    /// Whether the login waits for the connection to come back.
+    pub fn interrupted(&self) -> bool {
+        self.interrupted
+    }
+
+    pub fn advance(&self, event: Event) -> Step {
+        match event {
+            Event::Frame(frame) => self.on_frame(*frame),
+            Event::Connection(state) => self.on_connection(state),
+        }
+    }
+
+    fn on_frame(&self, frame: ServerMessage) -> Step {
+        match frame {
+            // The server may spell the nick the way it was first claimed.
+            ServerMessage::AuthOk { data, token }
+                if self.token.is_none() && data.eq_ignore_ascii_case(&self.name) =>
+            {
+                let register = ClientMessage::Register { data: data.clone() };
+                let login = Self {
+                    name: data,
+                    token: Some(token),
+                    ..self.clone()
+                };
+                Step::Waiting {
+                    login,
+                    send: vec![register],
+                }
+            }
+            ServerMessage::RegisterAck { data } if data == self.name => {
+                match &self.token {
+                    Some(token) => Step::Done {
+                        name: data,
+                        token: token.clone(),
+                    },
+                    None => Step::Ignored,
+                }
+            }
+            ServerMessage::AuthRejected { reason } | ServerMessage::RegisterRejected { reason } => {
+                Step::Failed(reason)
+            }
+            _ => Step::Ignored,
+        }
+    }
+
+    fn on_connection(&self, state: ConnectionState) -> Step {
+        match state {
+            ConnectionState::Reconnecting => Step::Waiting {
+                login: Self {
+                    token: None,
+                    interrupted: true,
+                    ..self.clone()
+                },
+                send: vec![],
+            },
+            ConnectionState::Open if self.interrupted => {
+                let login = Self {
+                    interrupted: false,
+                    ..self.clone()
+                };
+                let auth = login.auth();
+                Step::Waiting {
+                    login,
+                    send: vec![auth],
+                }
+            }
+            ConnectionState::Closed => Step::Failed("Connection lost, try again.".to_string()),
+            ConnectionState::Connecting | ConnectionState::Open => Step::Ignored,
+        }
+    }
+
+    fn auth(&self) -> ClientMessage {
+        ClientMessage::Auth {
+            data: self.name.clone(),
+            password: Some(self.password.clone()),
+            token: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(name: &str) -> ClientMessage {
+        ClientMessage::Auth {
+            data: name.into(),
+            password: Some("pw".into()),
+            token: None,
+        }
+    }
+
+    fn waiting(step: Step) -> (Login, Vec<ClientMessage>) {
+        match step {
+            Step::Waiting { login, send } => (login, send),
+            other => panic!("expected to wait, got {:?}", other),
+        }
+    }
+
+    fn frame(message: ServerMessage) -> Event {
+        Event::Frame(Box::new(message))
+    }
+
+    fn auth_ok(name: &str) -> Event {
+        frame(ServerMessage::AuthOk {
+            data: name.into(),
+            token: "t".into(),
+        })
+    }
+
+    fn register_ack(name: &str) -> Event {
+        frame(ServerMessage::RegisterAck { data: name.into() })
+    }
+
+    #[test]
+    fn logs_in_then_registers() {
+        let (login, first) = Login::start("alice".into(), "pw".into());
+        assert_eq!(first, auth("alice"));
+        assert_eq!(login.advance(register_ack("alice")), Step::Ignored);
+
+        let (login, send) = waiting(login.advance(auth_ok("Alice")));
+        assert_eq!(send, vec![ClientMessage::Register { data: "Alice".into() }]);
+        assert_eq!(login.advance(auth_ok("Alice")), Step::Ignored);
+        assert_eq!(login.advance(register_ack("bob")), Step::Ignored);
+        assert_eq!(
+            login.advance(register_ack("Alice")),
+            Step::Done {
+                name: "Alice".into(),
+                token: "t".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn rejections_end_the_login() {
+        let (login, _) = Login::start("alice".into(), "pw".into());
+        let rejected = frame(ServerMessage::AuthRejected { reason: "no".into() });
+        assert_eq!(login.advance(rejected), Step::Failed("no".into()));
+
+        let (login, _) = waiting(login.advance(auth_ok("alice")));
+        let rejected = frame(ServerMessage::RegisterRejected { reason: "taken".into() });
+        assert_eq!(login.advance(rejected), Step::Failed("taken".into()));
+    }
+
+    #[test]
+    fn starts_over_when_the_connection_comes_back() {
+        let (login, _) = Login::start("alice".into(), "pw".into());
+        assert_eq!(login.advance(Event::Connection(ConnectionState::Open)), Step::Ignored);
+
+        for login in [login.clone(), waiting(login.advance(auth_ok("alice"))).0] {
+            let lost = Event::Connection(ConnectionState::Reconnecting);
+            let (login, send) = waiting(login.advance(lost));
+            assert!(login.interrupted());
+            assert!(send.is_empty());
+            // A late answer from the old connection doesn't finish the login.
+            assert_eq!(login.advance(register_ack("alice")), Step::Ignored);
+
+            let (login, send) = waiting(login.advance(Event::Connection(ConnectionState::Open)));
+            assert!(!login.interrupted());
+            assert_eq!(send, vec![auth("alice")]);
+        }
+    }
+
+    #[test]
+    fn gives_up_when_the_connection_closes_for_good() {
+        let (login, _) = Login::start("alice".into(), "pw".into());
+        assert!(matches!(
+            login.advance(Event::Connection(ConnectionState::Closed)),
+            Step::Failed(_)
+        ));
+    }
+}
@@ -0,0 +1,85 @@
+//! Formatting of the millisecond timestamps the server stamps on messages.
+
+use js_sys::Date;
+use wasm_bindgen::JsValue;
+
+const MINUTE_MS: u64 = 60 * 1000;
+const HOUR_MS: u64 = 60 * MINUTE_MS;
+const DAY_MS: u64 = 24 * HOUR_MS;
+
+/// Current time in milliseconds since the Unix epoch.
+pub fn now() -> u64 {
+    Date::now() as u64
+}
+
+/// "just now", "5 min ago", "3 h ago", … — anything older than a week is left to
+/// [`date_label`].
+pub fn relative(now: u64, then: u64) -> String {
+    let elapsed = now.saturating_sub(then);
+    if elapsed < MINUTE_MS {
+        "just now".to_string()
+    } else if elapsed < HOUR_MS {
+        format!("{} min ago", elapsed / MINUTE_MS)
+    } else if elapsed < DAY_MS {
+        format!("{} h ago", elapsed / HOUR_MS)
+    } else if elapsed < 7 * DAY_MS {
+        let days = elapsed / DAY_MS;
+        format!("{} day{} ago", days, if days == 1 { "" } else { "s" })
+    } else {
+        String::new()
+    }
+}
+
+/// Local wall-clock time, e.g. `09:05`.
+pub fn clock(ms: u64) -> String {
+    let date = date(ms);
+    format!("{:02}:{:02}", date.get_hours(), date.get_minutes())
+}
+
+/// Full local date and time, used for tooltips.
+pub fn absolute(ms: u64) -> String {
+    String::from(date(ms).to_locale_string("default", &JsValue::UNDEFINED))
+}
+
+/// Identifies the local calendar day `ms` falls on.
+pub fn day(ms: u64) -> (u32, u32, u32) {
+    let date = date(ms);
+    (date.get_full_year(), date.get_month(), date.get_date())
+}
+
+/// "Today", "Yesterday" or the local date, for day separators.
+pub fn date_label(now: u64, ms: u64) -> String {
+    if day(ms) == day(now) {
+        "Today".to_string()
+    } else if day(ms) == day(now.saturating_sub(DAY_MS)) {
+        "Yesterday".to_string()
+    } else {
+        String::from(date(ms).to_locale_date_string("default", &JsValue::UNDEFINED))
+    }
+}
+
+fn date(ms: u64) -> Date {
+    Date::new(&JsValue::from_f64(ms as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_times() {
+        let now = 10 * DAY_MS;
+        assert_eq!(relative(now, now), "just now");
+        assert_eq!(relative(now, now - 59 * 1000), "just now");
+        assert_eq!(relative(now, now - 5 * MINUTE_MS), "5 min ago");
+        assert_eq!(relative(now, now - 3 * HOUR_MS - MINUTE_MS), "3 h ago");
+        assert_eq!(relative(now, now - DAY_MS), "1 day ago");
+        assert_eq!(relative(now, now - 6 * DAY_MS), "6 days ago");
+        assert_eq!(relative(now, now - 8 * DAY_MS), "");
+    }
+
+    #[test]
+    fn future_timestamps_are_just_now() {
+        assert_eq!(relative(1_000, 5_000), "just now");
+    }
+}